@@ -1,13 +1,36 @@
 #[cfg(windows)]
 extern crate winres;
 
-#[cfg(windows)]
+/// 使用 data/fonts/subset.py 生成，没有提交到仓库
+const K_BUNDLED_FONT: &str = "data/fonts/cjk-subset.otf";
+
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    bundled_font();
+    resources();
+}
+
+/// 启用了 bundled-font 但是还没有生成字体子集时直接报错，避免编译出没有内置字体的程序
+fn bundled_font() {
+    println!("cargo:rerun-if-changed={}", K_BUNDLED_FONT);
+    if std::env::var_os("CARGO_FEATURE_BUNDLED_FONT").is_some()
+        && !std::path::Path::new(K_BUNDLED_FONT).exists()
+    {
+        panic!(
+            "{} 不存在，启用 bundled-font 前先运行 data/fonts/subset.py 生成",
+            K_BUNDLED_FONT
+        );
+    }
+}
+
+#[cfg(windows)]
+fn resources() {
+    println!("cargo:rerun-if-changed=data/icon.ico");
     let mut res = winres::WindowsResource::new();
     res.set_icon("data/icon.ico");
     res.compile().unwrap();
 }
 
 #[cfg(unix)]
-fn main() {
-}
\ No newline at end of file
+fn resources() {
+}
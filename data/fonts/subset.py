@@ -0,0 +1,44 @@
+#!/usr/bin/env python3
+"""生成内置中文字体子集 cjk-subset.otf，只包含界面用到的字符
+
+启用 bundled-font feature 时嵌入程序，在系统中找不到中文字体时使用。
+需要 fonttools (pip install fonttools) 和一个支持中文的字体，例如 Noto Sans SC:
+
+    python3 data/fonts/subset.py NotoSansSC-Regular.otf
+"""
+
+import pathlib
+import subprocess
+import sys
+
+ROOT = pathlib.Path(__file__).resolve().parents[2]
+OUTPUT = pathlib.Path(__file__).resolve().parent / "cjk-subset.otf"
+
+# 常用的中文标点，界面文字之外的输入也能正常显示
+PUNCTUATION = "，。：；！？（）、《》“”‘’…·【】"
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+
+    chars = set(PUNCTUATION)
+    # 界面文字在语言文件中，源码中还有注释和少量不需要翻译的文字
+    sources = [*ROOT.glob("src/**/*.rs"), *ROOT.glob("data/i18n/*.json")]
+    for path in sources:
+        chars.update(c for c in path.read_text(encoding="utf-8") if ord(c) > 0x7F)
+
+    subprocess.run(
+        [
+            "pyftsubset",
+            sys.argv[1],
+            "--text=" + "".join(sorted(chars)),
+            "--output-file=" + str(OUTPUT),
+        ],
+        check=True,
+    )
+    print(f"{len(chars)} 个字符 -> {OUTPUT}")
+
+
+if __name__ == "__main__":
+    main()
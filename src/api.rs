@@ -1,13 +1,98 @@
-use anyhow::Ok;
-use serde::Deserialize;
+use anyhow::{Context, Ok};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::i18n::{self, t};
+
+/// sm.ms 在 token 无效时返回的 code
+const K_UNAUTHORIZED_CODE: &str = "unauthorized";
+
+/// token 无效或者已经被撤销，需要重新登录
+#[derive(Debug)]
+pub struct Unauthorized(pub String);
+
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("api.unauthorized", message = self.0))
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// 错误是否由 token 失效引起
+pub fn is_unauthorized(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| err.is::<Unauthorized>())
+}
+
+/// sm.ms 在相同的图片已经上传过时返回的 code
+const K_REPEATED_CODE: &str = "image_repeated";
+
+/// 相同的图片已经上传过，`url` 是 sm.ms 返回的已有链接
+#[derive(Debug)]
+pub struct Repeated(pub String);
+
+impl std::fmt::Display for Repeated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("api.error.image_repeated", message = self.0))
+    }
+}
+
+impl std::error::Error for Repeated {}
+
+/// 错误由重复上传引起时返回已有的链接
+pub fn repeated_url(err: &anyhow::Error) -> Option<&str> {
+    err.chain()
+        .find_map(|err| err.downcast_ref::<Repeated>())
+        .map(|repeated| repeated.0.as_str())
+}
+
+/// 解析需要 token 的接口返回的 json，HTTP 401 时返回 Unauthorized
+async fn parse<T: DeserializeOwned>(res: reqwest::Response) -> anyhow::Result<T> {
+    if res.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(Unauthorized(res.status().to_string()).into());
+    }
+    Ok(res.json::<T>().await?)
+}
 
 #[derive(Debug, Deserialize)]
 pub struct BaseResult {
     pub success: bool,
     pub message: String,
     pub code: String,
-    #[serde(rename(serialize = "RequestId", deserialize = "RequestId"))]
-    pub request_id: String,
+}
+
+impl BaseResult {
+    /// 请求失败时返回错误说明，token 失效时返回 Unauthorized
+    fn check(&self) -> anyhow::Result<()> {
+        if self.success {
+            return Ok(());
+        }
+        if self.code == K_UNAUTHORIZED_CODE {
+            return Err(Unauthorized(self.message.clone()).into());
+        }
+        // message 例如 "Image upload repeated limit, this image exists at: https://..."
+        if self.code == K_REPEATED_CODE {
+            let url = self
+                .message
+                .split_whitespace()
+                .find(|word| word.starts_with("http://") || word.starts_with("https://"));
+            if let Some(url) = url {
+                return Err(Repeated(url.to_owned()).into());
+            }
+        }
+        anyhow::bail!(self.error())
+    }
+
+    /// 已知的 code 使用语言文件中的说明，其他的显示 sm.ms 返回的 message
+    fn error(&self) -> String {
+        match i18n::lookup(&format!("api.error.{}", self.code)) {
+            Some(text) => i18n::format(text, &[("message", self.message.clone())]),
+            None => t!(
+                "api.error.unknown",
+                code = self.code,
+                message = self.message
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,13 +125,13 @@ pub async fn token(username: &str, password: &str) -> anyhow::Result<String> {
     // dbg!("{:?}", &d);
 
     if !d.base.success {
-        anyhow::bail!(d.base.message);
+        anyhow::bail!(d.base.error());
     }
 
     Ok(d.data.unwrap().token)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProfileData {
     pub username: String,
     pub email: String,
@@ -76,13 +161,11 @@ pub async fn profile(token: &str) -> anyhow::Result<ProfileData> {
         .send()
         .await?;
 
-    let d = res.json::<ProfileResult>().await?;
+    let d = parse::<ProfileResult>(res).await?;
 
     // dbg!("{:?}", &d);
 
-    if !d.base.success {
-        anyhow::bail!(d.base.message);
-    }
+    d.base.check()?;
 
     Ok(d.data.unwrap())
 }
@@ -95,18 +178,16 @@ pub async fn delete_image(token: &str, hash: &str) -> anyhow::Result<()> {
         .send()
         .await?;
 
-    let d = res.json::<BaseResult>().await?;
+    let d = parse::<BaseResult>(res).await?;
 
     // dbg!("{:?}", &d);
 
-    if !d.success {
-        anyhow::bail!(d.message);
-    }
+    d.check()?;
 
     Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct UploadHistoryData {
     pub width: i32,
     pub height: i32,
@@ -128,41 +209,62 @@ pub struct UploadHistoryResult {
 
     // 返回错误可能没有data数据
     data: Option<Vec<UploadHistoryData>>,
+
+    #[serde(rename = "TotalPages")]
+    total_pages: Option<u32>,
 }
 
-pub async fn upload_history(token: &str) -> anyhow::Result<Vec<UploadHistoryData>> {
+/// 获取一页上传历史，同时返回总页数；第一页是最新上传的图片
+pub async fn upload_history_page(
+    token: &str,
+    page: u32,
+) -> anyhow::Result<(Vec<UploadHistoryData>, u32)> {
     let client = reqwest::Client::new();
     let res = client
-        .get(format!("https://sm.ms/api/v2/upload_history?page={}", 0))
+        .get(format!("https://sm.ms/api/v2/upload_history?page={}", page))
         .header("Authorization", token)
         .send()
         .await?;
 
-    let d = res.json::<UploadHistoryResult>().await?;
+    let d = parse::<UploadHistoryResult>(res).await?;
 
-    if !d.base.success {
-        anyhow::bail!(d.base.message);
-    }
+    d.base.check()?;
 
-    Ok(d.data.unwrap())
+    Ok((d.data.unwrap_or_default(), d.total_pages.unwrap_or(1)))
 }
 
-pub async fn upload(token: &str, upload_file_path: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
+/// 上传成功后返回的图片信息
+#[derive(Debug, Deserialize)]
+pub struct UploadData {
+    pub hash: String,
+    pub url: String,
+}
 
-    let upload_file_path_p = std::path::Path::new(upload_file_path);
-    let filename = upload_file_path_p
-        .file_name()
-        .unwrap()
-        .to_os_string()
-        .into_string()
-        .ok()
-        .unwrap();
+#[derive(Debug, Deserialize)]
+pub struct UploadResult {
+    #[serde(flatten)]
+    base: BaseResult,
+
+    // 返回错误可能没有data数据
+    data: Option<UploadData>,
+}
+
+/// 上传内存中的图片数据，例如预处理过后的图片
+///
+/// `mime` 例如 "image/png"，不会再检查是否和内容一致，上传前先经过预处理的 `mime::check`
+pub async fn upload(
+    token: &str,
+    bytes: Vec<u8>,
+    filename: String,
+    mime: &str,
+) -> anyhow::Result<UploadData> {
+    let client = reqwest::Client::new();
 
     let form = reqwest::multipart::Form::new().part(
         "smfile",
-        reqwest::multipart::Part::bytes(tokio::fs::read(upload_file_path).await.unwrap())
-            .file_name(filename),
+        reqwest::multipart::Part::bytes(bytes)
+            .file_name(filename)
+            .mime_str(mime)?,
     );
 
     let res = client
@@ -172,11 +274,60 @@ pub async fn upload(token: &str, upload_file_path: &str) -> anyhow::Result<()> {
         .send()
         .await?;
 
-    let d = res.json::<BaseResult>().await?;
+    let d = parse::<UploadResult>(res).await?;
 
-    if !d.success {
-        anyhow::bail!(d.message);
+    d.base.check()?;
+
+    d.data.context(t!("api.missing_data"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(success: bool, code: &str, message: &str) -> BaseResult {
+        BaseResult {
+            success,
+            message: message.to_owned(),
+            code: code.to_owned(),
+        }
     }
 
-    Ok(())
+    #[test]
+    fn success_is_ok() {
+        assert!(result(true, "success", "").check().is_ok());
+    }
+
+    #[test]
+    fn repeated_returns_existing_url() {
+        let err = result(
+            false,
+            K_REPEATED_CODE,
+            "Image upload repeated limit, this image exists at: https://i.loli.net/2023/05/01/abc.png",
+        )
+        .check()
+        .unwrap_err();
+        assert_eq!(
+            repeated_url(&err),
+            Some("https://i.loli.net/2023/05/01/abc.png")
+        );
+        assert!(!is_unauthorized(&err));
+    }
+
+    #[test]
+    fn repeated_without_url_is_a_plain_error() {
+        let err = result(false, K_REPEATED_CODE, "Image upload repeated limit")
+            .check()
+            .unwrap_err();
+        assert_eq!(repeated_url(&err), None);
+    }
+
+    #[test]
+    fn unauthorized_is_detected() {
+        let err = result(false, K_UNAUTHORIZED_CODE, "Token invalid")
+            .check()
+            .unwrap_err();
+        assert!(is_unauthorized(&err));
+        assert_eq!(repeated_url(&err), None);
+    }
 }
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use globset::GlobSet;
+
+use anyhow::Context;
+
+use crate::{i18n::t, scan};
+
+/// 压缩包中单个文件的上限，避免恶意压缩包耗尽内存
+const K_MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+/// tar 缓存的总大小上限，超出时后面的文件在读取时重新解压
+const K_TAR_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// tar 只能顺序读取，读取其中一个文件时顺便缓存后面的图片，取出后从缓存中移除
+///
+/// 只缓存最近读取的一个压缩包
+static TAR_CACHE: Mutex<Option<TarCache>> = Mutex::new(None);
+
+struct TarCache {
+    path: PathBuf,
+    /// 压缩包修改后重新读取
+    modified: SystemTime,
+    images: HashMap<String, Vec<u8>>,
+    size: u64,
+}
+
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// 根据扩展名判断是否是支持的压缩包: .zip .tar .tar.gz .tgz
+pub fn is_archive(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// 压缩包内文件的显示名称，例如 "assets.zip!/icons/logo.png"
+pub fn entry_label(path: &str, entry: &str) -> String {
+    format!("{}!/{}", path, entry)
+}
+
+/// 压缩包内文件的文件名，不包含目录
+pub fn entry_filename(entry: &str) -> &str {
+    entry.rsplit('/').next().unwrap_or(entry)
+}
+
+/// 依次读取压缩包内的图片，不会解压到磁盘，同时只有一个文件在内存中
+pub fn visit_images(path: &Path, mut f: impl FnMut(String, Vec<u8>)) -> anyhow::Result<()> {
+    let include = image_glob()?;
+    visit(
+        path,
+        |name| include.is_match(name),
+        |entry, bytes| {
+            f(entry, bytes);
+            true
+        },
+    )
+}
+
+/// 读取压缩包内的一个文件
+///
+/// zip 可以直接读取；tar 读取时缓存后面的图片，按顺序上传时不需要每个文件都从头解压一次
+pub fn read_entry(path: &Path, entry: &str) -> anyhow::Result<Vec<u8>> {
+    let kind = kind(path).with_context(|| t!("archive.unsupported", path = path.display()))?;
+    let found = match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(open(path)?)?;
+            let found = match zip.by_name(entry) {
+                Ok(file) => Some(read_limited(file)?),
+                Err(zip::result::ZipError::FileNotFound) => None,
+                Err(err) => return Err(err.into()),
+            };
+            found
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => take_tar_entry(path, entry)?,
+    };
+    found.with_context(|| {
+        t!(
+            "archive.entry_missing",
+            entry = entry_label(&path.display().to_string(), entry)
+        )
+    })
+}
+
+/// 释放 tar 的缓存，例如退出登录后等待的文件可能很久之后才上传
+pub fn clear_cache() {
+    *lock_tar_cache() = None;
+}
+
+fn take_tar_entry(path: &Path, entry: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| t!("archive.unreadable", path = path.display()))?;
+
+    // 解压时持有锁，同时上传同一个压缩包内的文件时只解压一次
+    let mut cache = lock_tar_cache();
+    if let Some(cached) = cache
+        .as_mut()
+        .filter(|cached| cached.path == path && cached.modified == modified)
+    {
+        if let Some(bytes) = cached.images.remove(entry) {
+            cached.size -= bytes.len() as u64;
+            return Ok(Some(bytes));
+        }
+    }
+
+    // 缓存中没有时可能是重试上传或者缓存已满，从头读取到 `entry`，再缓存后面的图片直到上限
+    let include = image_glob()?;
+    let mut found = None;
+    let mut cached = TarCache {
+        path: path.to_owned(),
+        modified,
+        images: HashMap::new(),
+        size: 0,
+    };
+    visit(
+        path,
+        |name| name == entry || include.is_match(name),
+        |name, bytes| {
+            if found.is_none() {
+                if name == entry {
+                    found = Some(bytes);
+                }
+                return true;
+            }
+            if cached.size + bytes.len() as u64 > K_TAR_CACHE_SIZE {
+                return false;
+            }
+            cached.size += bytes.len() as u64;
+            cached.images.insert(name, bytes);
+            true
+        },
+    )?;
+    *cache = Some(cached);
+    Ok(found)
+}
+
+fn lock_tar_cache() -> std::sync::MutexGuard<'static, Option<TarCache>> {
+    TAR_CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn image_glob() -> anyhow::Result<GlobSet> {
+    scan::build_glob_set(&[scan::K_DEFAULT_INCLUDE.to_owned()])
+}
+
+fn open(path: &Path) -> anyhow::Result<BufReader<File>> {
+    Ok(BufReader::new(File::open(path).with_context(|| {
+        t!("archive.unreadable", path = path.display())
+    })?))
+}
+
+/// 依次读取压缩包内 `matches` 的文件，`f` 返回 false 时停止
+fn visit(
+    path: &Path,
+    matches: impl Fn(&str) -> bool,
+    mut f: impl FnMut(String, Vec<u8>) -> bool,
+) -> anyhow::Result<()> {
+    let kind = kind(path).with_context(|| t!("archive.unsupported", path = path.display()))?;
+    let file = open(path)?;
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for i in 0..zip.len() {
+                let entry = zip.by_index(i)?;
+                if !entry.is_file() || !matches(entry.name()) {
+                    continue;
+                }
+                let name = entry.name().to_owned();
+                if !f(name, read_limited(entry)?) {
+                    break;
+                }
+            }
+        }
+        ArchiveKind::Tar => visit_tar(tar::Archive::new(file), matches, f)?,
+        ArchiveKind::TarGz => visit_tar(
+            tar::Archive::new(flate2::read::GzDecoder::new(file)),
+            matches,
+            f,
+        )?,
+    }
+
+    Ok(())
+}
+
+fn visit_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    matches: impl Fn(&str) -> bool,
+    mut f: impl FnMut(String, Vec<u8>) -> bool,
+) -> anyhow::Result<()> {
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+        if !matches(&name) {
+            continue;
+        }
+        if !f(name, read_limited(entry)?) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_limited(reader: impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(K_MAX_ENTRY_SIZE + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > K_MAX_ENTRY_SIZE {
+        anyhow::bail!(t!("archive.entry_too_large"));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const K_FILES: [(&str, &[u8]); 3] = [
+        ("a.png", b"png"),
+        ("notes.txt", b"text"),
+        ("dir/b.JPG", b"jpeg"),
+    ];
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sm-ms-rs-{}-{}", std::process::id(), name))
+    }
+
+    fn write_zip(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, bytes) in K_FILES {
+            zip.start_file(name, Default::default()).unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    fn write_tar(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        let file = File::create(&path).unwrap();
+        let mut builder = if name.ends_with(".tar") {
+            tar::Builder::new(Box::new(file) as Box<dyn Write>)
+        } else {
+            let gz = flate2::write::GzEncoder::new(file, Default::default());
+            tar::Builder::new(Box::new(gz) as Box<dyn Write>)
+        };
+        for (name, bytes) in K_FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(bytes.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, bytes).unwrap();
+        }
+        builder.into_inner().unwrap().flush().unwrap();
+        path
+    }
+
+    fn check_archive(path: &Path) {
+        let mut visited = vec![];
+        visit_images(path, |name, bytes| visited.push((name, bytes))).unwrap();
+        assert_eq!(
+            visited,
+            [
+                ("a.png".to_owned(), b"png".to_vec()),
+                ("dir/b.JPG".to_owned(), b"jpeg".to_vec())
+            ]
+        );
+
+        assert_eq!(read_entry(path, "dir/b.JPG").unwrap(), b"jpeg");
+        assert_eq!(read_entry(path, "a.png").unwrap(), b"png");
+        // 重试上传时再次读取
+        assert_eq!(read_entry(path, "a.png").unwrap(), b"png");
+        assert!(read_entry(path, "missing.png").is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn zip_entries() {
+        check_archive(&write_zip("test.zip"));
+    }
+
+    #[test]
+    fn tar_entries() {
+        check_archive(&write_tar("test.tar"));
+        check_archive(&write_tar("test.tar.gz"));
+    }
+
+    #[test]
+    fn labels() {
+        assert!(is_archive(Path::new("a.TGZ")));
+        assert!(!is_archive(Path::new("a.gz")));
+        assert_eq!(entry_label("a.zip", "dir/b.png"), "a.zip!/dir/b.png");
+        assert_eq!(entry_filename("dir/b.png"), "b.png");
+    }
+}
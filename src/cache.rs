@@ -1,33 +1,478 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    api, config, dedup,
+    i18n::{self, t},
+    preprocess::PreprocessOptions,
+    secret::EncryptedToken,
+    util,
+    watermark::WatermarkOptions,
+};
+
+/// 保存在配置目录中
+pub const K_CACHE_FILE: &str = "sm_ms_cache.json";
+
+/// 每次保存前备份上一次可以读取的缓存文件，缓存文件损坏时从备份恢复
+const K_BACKUP_SUFFIX: &str = ".bak";
+
+/// 第 i 个函数把版本 i 的数据迁移到版本 i + 1，修改格式时在末尾添加
+const K_MIGRATIONS: [fn(&mut Value) -> anyhow::Result<()>; 2] = [migrate_v0, migrate_v1];
+
+/// 当前的缓存文件版本
+const K_CACHE_VERSION: u32 = K_MIGRATIONS.len() as u32;
+
+/// 旧版本只有一个账号，迁移后使用这个名字
+const K_LEGACY_ACCOUNT: &str = "default";
 
-pub const K_CACHE_PATH: &str = "./sm_ms_cache.json";
+/// 界面线程和后台任务都会修改缓存文件，读取、修改、写入的过程需要串行
+static CACHE_LOCK: Mutex<()> = Mutex::new(());
 
-#[derive(Debug, Serialize, Deserialize)]
+/// 保存的账号
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub name: String,
+    pub encrypted_token: EncryptedToken,
+    /// 最后一次获取到的账号信息
+    #[serde(default)]
+    pub profile: Option<api::ProfileData>,
+    /// 水印按账号保存，不同账号可以使用不同的水印
+    #[serde(default)]
+    pub watermark: WatermarkOptions,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct SmMsCacheData {
-    pub token: Option<String>,
+    /// 缓存文件格式的版本，保存时写入 K_CACHE_VERSION
+    #[serde(default)]
+    version: u32,
+
+    #[serde(default)]
+    pub accounts: Vec<Account>,
+
+    /// 当前使用的账号名
+    #[serde(default)]
+    pub current_account: Option<String>,
+
+    /// 其中的水印设置不使用，保存在每个账号中
+    #[serde(default)]
+    pub preprocess: PreprocessOptions,
+
+    /// 同时上传的数量
+    #[serde(default)]
+    pub upload_concurrency: Option<usize>,
+
+    /// 界面字体，为空时自动查找支持中文的系统字体
+    #[serde(default)]
+    pub font_path: String,
+
+    /// 界面语言，为 None 时跟随系统
+    #[serde(default)]
+    pub language: Option<i18n::Lang>,
 }
 
 impl SmMsCacheData {
-    /// 获取或则创建缓存文件
+    /// 读取缓存文件，不存在或者无法读取时返回 None，第一次保存时创建
     pub fn get_or_create() -> Option<SmMsCacheData> {
-        let cache_path = std::path::Path::new(K_CACHE_PATH);
-        if cache_path.exists() {
-            Self::from().ok()
-        } else {
-            std::fs::File::create(cache_path).unwrap();
-            None
+        match Self::from() {
+            Ok(data) => Some(data),
+            Err(err) => {
+                if config::path(K_CACHE_FILE).exists() {
+                    eprintln!("{}", t!("cache.unreadable", error = format!("{:#}", err)));
+                }
+                None
+            }
         }
     }
 
-    pub fn save(data: Self) -> anyhow::Result<()> {
-        let cache_path = std::path::Path::new(K_CACHE_PATH);
-        Ok(std::fs::write(cache_path, serde_json::to_vec(&data)?)?)
+    /// 读取缓存文件，还没有缓存文件时返回默认值
+    ///
+    /// 文件存在但是无法读取时返回错误，不能用默认值代替，否则保存时会覆盖保存的账号
+    pub fn load() -> anyhow::Result<Self> {
+        let _lock = lock();
+        Self::load_unlocked()
     }
 
-    pub fn from() -> anyhow::Result<Self> {
-        let cache_path = std::path::Path::new(K_CACHE_PATH);
-        Ok(serde_json::from_slice::<SmMsCacheData>(
-            std::fs::read(cache_path)?.as_ref(),
+    /// 读取最新的缓存文件，修改后保存；无法读取时不会写入
+    pub fn update<R>(f: impl FnOnce(&mut Self) -> anyhow::Result<R>) -> anyhow::Result<R> {
+        let _lock = lock();
+        let mut data = Self::load_unlocked()?;
+        let res = f(&mut data)?;
+        Self::save_unlocked(data)?;
+        Ok(res)
+    }
+
+    fn load_unlocked() -> anyhow::Result<Self> {
+        let cache_path = config::path(K_CACHE_FILE);
+        if !cache_path.exists() && !backup_path(&cache_path).exists() {
+            return Ok(Self::default());
+        }
+        Self::from_unlocked()
+    }
+
+    /// 写入临时文件后重命名，替换前备份原文件
+    fn save_unlocked(mut data: Self) -> anyhow::Result<()> {
+        data.version = K_CACHE_VERSION;
+        let cache_path = config::path(K_CACHE_FILE);
+
+        // 只备份可以解析的文件，避免损坏的文件覆盖备份
+        let valid = std::fs::read(&cache_path)
+            .is_ok_and(|bytes| serde_json::from_slice::<Value>(&bytes).is_ok());
+        if valid {
+            std::fs::copy(&cache_path, backup_path(&cache_path))?;
+        }
+
+        Ok(util::write_atomic(
+            &cache_path,
+            &serde_json::to_vec(&data)?,
         )?)
     }
+
+    /// 读取缓存文件并迁移到当前版本，文件损坏时从备份恢复
+    pub fn from() -> anyhow::Result<Self> {
+        let _lock = lock();
+        Self::from_unlocked()
+    }
+
+    fn from_unlocked() -> anyhow::Result<Self> {
+        let cache_path = config::path(K_CACHE_FILE);
+        let backup_path = backup_path(&cache_path);
+        // 旧版本的 get_or_create 会留下空文件，没有备份时当作还没有缓存
+        if is_blank(&cache_path) && !backup_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let (data, migrated) = match Self::read(&cache_path) {
+            Ok(res) => res,
+            // 版本过新等错误不能用备份代替，否则写回时会覆盖新版本的文件
+            Err(err) if !is_corrupt(&err) => return Err(err),
+            Err(err) => {
+                // 保留原来的错误，备份也无法读取时返回
+                let res = Self::read(&backup_path).map_err(|_| err)?;
+                eprintln!("{}", t!("cache.restored"));
+                (res.0, true)
+            }
+        };
+
+        // 迁移或者恢复后立即写回，同时覆盖备份，避免备份中留下旧版本的明文 token
+        if migrated {
+            let bytes = serde_json::to_vec(&data)?;
+            util::write_atomic(&backup_path, &bytes)?;
+            util::write_atomic(&cache_path, &bytes)?;
+        }
+
+        Ok(data)
+    }
+
+    /// 返回的 bool 表示是否进行了迁移
+    fn read(path: &Path) -> anyhow::Result<(Self, bool)> {
+        let mut value: Value = serde_json::from_slice(&std::fs::read(path)?)
+            .with_context(|| t!("cache.invalid", path = path.display()))?;
+
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+        if version > K_CACHE_VERSION {
+            anyhow::bail!(t!(
+                "cache.too_new",
+                path = path.display(),
+                version = version,
+                supported = K_CACHE_VERSION
+            ));
+        }
+        for migrate in &K_MIGRATIONS[version as usize..] {
+            migrate(&mut value)?;
+        }
+
+        let mut data: Self = serde_json::from_value(value)?;
+        data.version = K_CACHE_VERSION;
+        Ok((data, version < K_CACHE_VERSION))
+    }
+
+    /// 当前使用的账号
+    pub fn account(&self) -> Option<&Account> {
+        let name = self.current_account.as_deref()?;
+        self.find_account(name)
+    }
+
+    pub fn find_account(&self, name: &str) -> Option<&Account> {
+        self.accounts.iter().find(|account| account.name == name)
+    }
+
+    pub fn find_account_mut(&mut self, name: &str) -> Option<&mut Account> {
+        self.accounts
+            .iter_mut()
+            .find(|account| account.name == name)
+    }
+
+    /// 使用 `account` 的水印设置，没有账号时不加水印
+    pub fn preprocess_for(&self, account: Option<&Account>) -> PreprocessOptions {
+        PreprocessOptions {
+            watermark: account
+                .map(|account| account.watermark.clone())
+                .unwrap_or_default(),
+            ..self.preprocess.clone()
+        }
+    }
+
+    /// 加密保存账号的 token 并切换到这个账号，`passphrase` 为空时使用本机密钥
+    pub fn add_account(
+        &mut self,
+        name: &str,
+        token: &str,
+        passphrase: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let (profile, watermark) = match self.find_account(name) {
+            Some(account) => (account.profile.clone(), account.watermark.clone()),
+            None => Default::default(),
+        };
+        let account = Account {
+            name: name.to_owned(),
+            encrypted_token: EncryptedToken::encrypt(token, passphrase)?,
+            profile,
+            watermark,
+        };
+
+        // 同名账号会被替换
+        self.current_account = Some(account.name.clone());
+        match self.accounts.iter_mut().find(|a| a.name == account.name) {
+            Some(a) => *a = account,
+            None => self.accounts.push(account),
+        }
+        Ok(())
+    }
+
+    pub fn remove_account(&mut self, name: &str) {
+        self.accounts.retain(|account| account.name != name);
+        if self.current_account.as_deref() == Some(name) {
+            self.current_account = None;
+        }
+    }
+
+    pub fn set_profile(&mut self, name: &str, profile: api::ProfileData) {
+        if let Some(account) = self.accounts.iter_mut().find(|a| a.name == name) {
+            account.profile = Some(profile);
+        }
+    }
+}
+
+/// 持有锁的线程 panic 后缓存文件仍然是完整的，可以继续使用
+fn lock() -> std::sync::MutexGuard<'static, ()> {
+    CACHE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// 文件为空或者只有空白字符
+fn is_blank(path: &Path) -> bool {
+    std::fs::read(path).is_ok_and(|bytes| bytes.iter().all(u8::is_ascii_whitespace))
+}
+
+/// 无法读取或者解析，这时才从备份恢复
+fn is_corrupt(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>().is_some()
+        || err.downcast_ref::<serde_json::Error>().is_some()
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(K_BACKUP_SUFFIX);
+    PathBuf::from(backup)
+}
+
+/// 版本 0 只有一个 token（旧版本明文保存的 token 或者加密后的 encrypted_token），转换为账号
+fn migrate_v0(value: &mut Value) -> anyhow::Result<()> {
+    let data = value.as_object_mut().context(t!("cache.invalid_data"))?;
+    let token = data.remove("token");
+    let encrypted_token = data.remove("encrypted_token");
+
+    // 明文 token 使用本机密钥加密
+    let encrypted_token = match (token, encrypted_token) {
+        (Some(Value::String(token)), _) => {
+            serde_json::to_value(EncryptedToken::encrypt(&token, None)?)?
+        }
+        (_, Some(encrypted_token)) if !encrypted_token.is_null() => encrypted_token,
+        _ => return Ok(()),
+    };
+
+    data.insert(
+        "accounts".to_owned(),
+        json!([{ "name": K_LEGACY_ACCOUNT, "encrypted_token": encrypted_token }]),
+    );
+    data.insert("current_account".to_owned(), json!(K_LEGACY_ACCOUNT));
+    dedup::DedupIndex::migrate_legacy(K_LEGACY_ACCOUNT);
+    Ok(())
+}
+
+/// 版本 1 的水印设置保存在 preprocess 中，所有账号共用，复制到每个账号
+fn migrate_v1(value: &mut Value) -> anyhow::Result<()> {
+    let data = value.as_object_mut().context(t!("cache.invalid_data"))?;
+    let watermark = data
+        .get_mut("preprocess")
+        .and_then(Value::as_object_mut)
+        .and_then(|preprocess| preprocess.remove("watermark"));
+    let Some(watermark) = watermark else {
+        return Ok(());
+    };
+
+    let accounts = data.get_mut("accounts").and_then(Value::as_array_mut);
+    for account in accounts.into_iter().flatten() {
+        if let Some(account) = account.as_object_mut() {
+            account.insert("watermark".to_owned(), watermark.clone());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 迁移旧的去重记录时会用到配置目录，其他测试可能已经初始化过
+    fn init_config() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sm-ms-rs-test-{}", std::process::id()));
+        let _ = config::init(Some(dir.clone()), false);
+        dir
+    }
+
+    /// 写入临时文件后按正常流程读取
+    fn read_value(name: &str, value: Value) -> anyhow::Result<(SmMsCacheData, bool)> {
+        let path = init_config().join(name);
+        std::fs::write(&path, serde_json::to_vec(&value)?)?;
+        let res = SmMsCacheData::read(&path);
+        std::fs::remove_file(&path)?;
+        res
+    }
+
+    #[test]
+    fn migrate_v0_to_current() {
+        let encrypted = EncryptedToken::encrypt("token", Some("passphrase")).unwrap();
+        let watermark = WatermarkOptions {
+            enabled: true,
+            text: "sm.ms".to_owned(),
+            ..Default::default()
+        };
+        let value = json!({
+            "encrypted_token": encrypted,
+            "preprocess": { "compress": true, "watermark": watermark },
+        });
+
+        let (data, migrated) = read_value("v0.json", value).unwrap();
+        assert!(migrated);
+        assert_eq!(data.version, K_CACHE_VERSION);
+        assert_eq!(data.current_account.as_deref(), Some(K_LEGACY_ACCOUNT));
+        assert!(data.preprocess.compress);
+        assert!(!data.preprocess.watermark.enabled);
+
+        let account = data.account().unwrap();
+        assert_eq!(
+            account.encrypted_token.decrypt(Some("passphrase")).unwrap(),
+            "token"
+        );
+        assert!(account.watermark.enabled);
+        assert_eq!(account.watermark.text, "sm.ms");
+    }
+
+    #[test]
+    fn migrate_v1_copies_watermark_to_accounts() {
+        let encrypted = EncryptedToken::encrypt("token", Some("passphrase")).unwrap();
+        let value = json!({
+            "version": 1,
+            "accounts": [
+                { "name": "a", "encrypted_token": encrypted },
+                { "name": "b", "encrypted_token": encrypted },
+            ],
+            "preprocess": { "watermark": { "enabled": true } },
+        });
+
+        let (data, migrated) = read_value("v1.json", value).unwrap();
+        assert!(migrated);
+        assert!(data
+            .accounts
+            .iter()
+            .all(|account| account.watermark.enabled));
+    }
+
+    #[test]
+    fn v0_without_token_has_no_account() {
+        let (data, migrated) = read_value("empty.json", json!({})).unwrap();
+        assert!(migrated);
+        assert!(data.accounts.is_empty());
+        assert!(data.current_account.is_none());
+    }
+
+    #[test]
+    fn current_version_is_not_migrated() {
+        let value = serde_json::to_value(SmMsCacheData {
+            version: K_CACHE_VERSION,
+            ..Default::default()
+        })
+        .unwrap();
+        let (_, migrated) = read_value("current.json", value).unwrap();
+        assert!(!migrated);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let value = json!({ "version": K_CACHE_VERSION + 1 });
+        assert!(read_value("newer.json", value).is_err());
+    }
+
+    /// 写入配置目录中的缓存文件和备份后读取，返回读取结果和之后的缓存文件内容
+    ///
+    /// 持有锁，避免和其他测试同时修改这两个文件
+    fn from_files(cache: &[u8], backup: Option<&[u8]>) -> (anyhow::Result<SmMsCacheData>, Vec<u8>) {
+        init_config();
+        let _lock = lock();
+        let cache_path = config::path(K_CACHE_FILE);
+        let backup_path = backup_path(&cache_path);
+        std::fs::write(&cache_path, cache).unwrap();
+        match backup {
+            Some(backup) => std::fs::write(&backup_path, backup).unwrap(),
+            None => {
+                let _ = std::fs::remove_file(&backup_path);
+            }
+        }
+
+        let res = SmMsCacheData::from_unlocked();
+        (res, std::fs::read(&cache_path).unwrap())
+    }
+
+    fn current(font_path: &str) -> Vec<u8> {
+        serde_json::to_vec(&SmMsCacheData {
+            version: K_CACHE_VERSION,
+            font_path: font_path.to_owned(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn blank_file_without_backup_is_no_cache() {
+        for cache in [&b""[..], b" \n"] {
+            let (res, after) = from_files(cache, None);
+            assert!(res.unwrap().accounts.is_empty());
+            assert_eq!(after, cache);
+        }
+    }
+
+    #[test]
+    fn corrupt_file_is_restored_from_backup() {
+        let (res, after) = from_files(b"{", Some(&current("backup")));
+        assert_eq!(res.unwrap().font_path, "backup");
+        let restored: SmMsCacheData = serde_json::from_slice(&after).unwrap();
+        assert_eq!(restored.font_path, "backup");
+    }
+
+    #[test]
+    fn newer_version_is_not_replaced_by_backup() {
+        let newer = serde_json::to_vec(&json!({ "version": K_CACHE_VERSION + 1 })).unwrap();
+        let (res, after) = from_files(&newer, Some(&current("backup")));
+        assert!(res.is_err());
+        assert_eq!(after, newer);
+    }
 }
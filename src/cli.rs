@@ -0,0 +1,316 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use tokio::sync::Notify;
+
+use crate::{
+    cache, config, convert, dedup,
+    i18n::t,
+    preflight,
+    queue::{self, UploadQueue, UploadStatus},
+    remote,
+    rename::RenamePolicy,
+    scan, secret, util,
+};
+
+/// sm.ms 图床客户端，不带参数时打开图形界面
+#[derive(Parser)]
+#[command(name = "sm-ms-rs", version)]
+pub struct Cli {
+    /// sm.ms API token，默认使用图形界面登录后保存的 token
+    #[arg(long, env = "SM_MS_TOKEN", global = true, hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// 使用保存的账号，默认使用图形界面中最后选择的账号
+    #[arg(long, env = "SM_MS_ACCOUNT", global = true)]
+    pub account: Option<String>,
+
+    /// 保存 token 和设置的目录，默认使用平台配置目录，例如 Linux 上的 ~/.config/sm-ms-rs
+    #[arg(long, env = config::K_CONFIG_DIR_ENV, global = true)]
+    pub config_dir: Option<PathBuf>,
+
+    /// 便携模式，配置保存在可执行文件所在目录；可执行文件旁边存在 sm_ms_portable 文件时自动启用
+    #[arg(long, global = true)]
+    pub portable: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// 上传文件或文件夹
+    Upload(UploadArgs),
+}
+
+#[derive(Args)]
+pub struct UploadArgs {
+    /// 要上传的文件，文件夹会被递归扫描，zip/tar 压缩包会上传其中的图片，
+    /// http(s) 开头的 url 会先下载再上传，`-` 表示从标准输入读取
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// 上传使用的文件名，只能在上传一个文件时使用；标准输入默认为 stdin.png 这样的名字
+    #[arg(long)]
+    pub filename: Option<String>,
+
+    /// 上传使用的文件名规则，默认使用图形界面中的设置
+    #[arg(long, value_enum)]
+    pub rename: Option<RenamePolicy>,
+
+    /// `--rename template` 使用的模板，支持 {name} {hash} {uuid} {yyyy} {MM} {dd} {HH} {mm} {ss}
+    #[arg(long)]
+    pub name_template: Option<String>,
+
+    /// 扫描文件夹时只上传匹配的文件，可以指定多次
+    #[arg(long)]
+    pub include: Vec<String>,
+
+    /// 扫描文件夹时跳过匹配的文件，可以指定多次
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// 扫描文件夹的最大深度，0 表示只扫描当前目录
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// 扫描时跟随符号链接
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// 只列出将要上传的文件
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// 把本地路径和上传后的 url 写入 JSON 清单
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// 同时上传的数量
+    #[arg(long, default_value_t = queue::K_DEFAULT_CONCURRENCY)]
+    pub concurrency: usize,
+}
+
+pub fn run(token: Option<String>, account: Option<String>, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Upload(args) => upload(token, account, args),
+    }
+}
+
+fn upload(token: Option<String>, account: Option<String>, args: UploadArgs) -> anyhow::Result<()> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let cache_data = cache::SmMsCacheData::load()?;
+
+    // 上传记录和水印按账号保存，指定 --token 时也使用账号的设置
+    let account = match account.as_deref() {
+        Some(name) => Some(
+            cache_data
+                .find_account(name)
+                .with_context(|| t!("cli.no_account", name = name))?,
+        ),
+        None => cache_data.account(),
+    };
+    let mut options = cache_data.preprocess_for(account);
+    if let Some(policy) = args.rename {
+        options.rename.policy = policy;
+    }
+    if let Some(template) = args.name_template {
+        options.rename.template = template;
+    }
+    let scan_options = scan::ScanOptions {
+        include: args.include,
+        exclude: args.exclude,
+        max_depth: args.max_depth,
+        follow_symlinks: args.follow_symlinks,
+    };
+
+    let mut files = vec![];
+    // 远程图片在上传时下载并校验
+    let mut urls = vec![];
+    let mut stdin = None;
+    for path in &args.paths {
+        let text = path.to_string_lossy();
+        if text == "-" {
+            if stdin.is_none() {
+                let bytes = rt
+                    .block_on(util::read_limited(
+                        tokio::io::stdin(),
+                        util::K_MAX_INPUT_SIZE,
+                    ))
+                    .context(t!("cli.stdin"))?;
+                stdin = Some((stdin_filename(&bytes), bytes));
+            }
+        } else if remote::is_url(&text) {
+            urls.push(text.into_owned());
+        } else if path.is_dir() {
+            let result = scan::scan(path, &scan_options, &Default::default())?;
+            for warning in &result.warnings {
+                eprintln!("{}", warning);
+            }
+            files.extend(result.files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    // 在发起网络请求前列出所有问题，标准输入的内容跟随预览一起保存
+    let mut checked = vec![];
+    for file in files {
+        let sources =
+            preflight::UploadPreview::read_source(&file, &options, |preview, _| (preview, None));
+        checked.extend(sources);
+    }
+    if let Some((filename, bytes)) = stdin {
+        let preview =
+            preflight::UploadPreview::from_bytes(Path::new(&filename), None, &bytes, &options);
+        checked.push((preview, Some((filename, bytes))));
+    }
+
+    let mut previews = vec![];
+    let mut invalid = 0;
+    for (preview, stdin) in checked {
+        if preview.is_ok() {
+            previews.push((preview, stdin));
+        } else {
+            invalid += 1;
+            for problem in &preview.problems {
+                eprintln!("{}: {}", preview.label(), problem);
+            }
+        }
+    }
+
+    if args.filename.is_some() && previews.len() + urls.len() > 1 {
+        anyhow::bail!(t!("cli.filename_single"));
+    }
+
+    if args.dry_run {
+        for (preview, _) in &previews {
+            println!("{}\t{}", preview.label(), preview.summary());
+            for note in &preview.notes {
+                println!("\t{}", note);
+            }
+        }
+        for url in &urls {
+            println!("{}", t!("cli.remote", url = url));
+        }
+        println!("{}", t!("cli.total", count = previews.len() + urls.len()));
+        // 和实际上传一样，有文件没有通过检查时返回错误，脚本可以先试运行
+        if invalid > 0 {
+            anyhow::bail!(t!("cli.invalid", count = invalid));
+        }
+        return Ok(());
+    }
+
+    let token = match token {
+        Some(token) => token,
+        None => account
+            .context(t!("cli.no_token"))?
+            .encrypted_token
+            .decrypt(secret::env_passphrase().as_deref())?,
+    };
+    let account = account.map(|account| account.name.clone());
+    let dedup = dedup::DedupIndex::shared(account.as_deref());
+
+    let queue = UploadQueue::default();
+    for (preview, stdin) in previews {
+        match stdin {
+            Some((name, bytes)) => queue.add_bytes(
+                name,
+                bytes,
+                args.filename.clone(),
+                options.clone(),
+                account.clone(),
+            ),
+            None => queue.add(
+                preview.path.display().to_string(),
+                preview.entry,
+                args.filename.clone(),
+                options.clone(),
+                account.clone(),
+            ),
+        }
+    }
+    for url in urls {
+        queue.add(
+            url,
+            None,
+            args.filename.clone(),
+            options.clone(),
+            account.clone(),
+        );
+    }
+
+    let notify = Arc::new(Notify::new());
+    let on_change = notify.clone();
+    queue.start(
+        rt.handle(),
+        account.clone(),
+        token,
+        dedup,
+        args.concurrency,
+        Arc::new(move || on_change.notify_one()),
+    );
+    rt.block_on(async {
+        while !queue.summary().is_finished() {
+            // token 失效时文件会回到等待状态，不会结束
+            if queue.take_expired(account.as_deref()) {
+                anyhow::bail!(t!("cli.token_expired"));
+            }
+            notify.notified().await;
+        }
+        Ok(())
+    })?;
+
+    for item in queue.items() {
+        match item.status {
+            UploadStatus::Failed => eprintln!(
+                "[{}] {}: {}",
+                item.status.label(),
+                item.label(),
+                item.messages.join("; ")
+            ),
+            _ => println!(
+                "[{}] {} {}",
+                item.status.label(),
+                item.label(),
+                item.url.unwrap_or_default()
+            ),
+        }
+    }
+
+    if let Some(manifest) = &args.manifest {
+        queue.write_manifest(manifest)?;
+    }
+
+    let summary = queue.summary();
+    println!(
+        "{}",
+        t!(
+            "cli.summary",
+            done = summary.done,
+            duplicate = summary.duplicate,
+            failed = summary.failed + invalid
+        )
+    );
+
+    if summary.failed + invalid > 0 {
+        anyhow::bail!(t!("cli.failed", count = summary.failed + invalid));
+    }
+
+    Ok(())
+}
+
+/// 根据内容生成标准输入的文件名，例如 stdin.png
+fn stdin_filename(bytes: &[u8]) -> String {
+    match image::guess_format(bytes) {
+        Ok(format) => util::replace_extension("stdin", format),
+        Err(_) if convert::is_svg(bytes) => "stdin.svg".to_owned(),
+        Err(_) => "stdin".to_owned(),
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::{cache, dedup, i18n::t, queue};
+
+/// 指定配置目录的环境变量，和 `--config-dir` 相同
+pub const K_CONFIG_DIR_ENV: &str = "SM_MS_CONFIG_DIR";
+
+/// 可执行文件旁边存在这个文件时使用便携模式，配置保存在可执行文件所在目录
+pub const K_PORTABLE_MARKER: &str = "sm_ms_portable";
+
+/// 每个账号单独的缓存目录，例如上传记录
+const K_ACCOUNTS_DIR: &str = "accounts";
+
+/// 平台配置目录下的子目录，例如 Linux 上的 ~/.config/sm-ms-rs
+const K_APP_DIR: &str = "sm-ms-rs";
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// 迁移等操作的提示，命令行模式打印出来，图形界面启动后显示
+static NOTES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// 确定配置目录并迁移旧版本保存在当前工作目录的文件，需要在读取任何配置前调用
+///
+/// 优先级: `dir`（`--config-dir` 或环境变量） > 便携模式 > 平台配置目录
+pub fn init(dir: Option<PathBuf>, portable: bool) -> anyhow::Result<()> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None if portable || portable_marker_exists() => exe_dir()?,
+        None => platform_dir().context(t!("config.no_dir"))?,
+    };
+
+    std::fs::create_dir_all(&dir)
+        .with_context(|| t!("config.create_failed", path = dir.display()))?;
+    migrate(&dir);
+
+    CONFIG_DIR
+        .set(dir)
+        .map_err(|_| anyhow::anyhow!(t!("config.initialized")))
+}
+
+/// 配置目录，没有调用 `init` 时使用平台配置目录，无法确定时使用当前工作目录
+pub fn dir() -> &'static Path {
+    CONFIG_DIR.get_or_init(|| {
+        let dir = platform_dir().unwrap_or_else(|| PathBuf::from("."));
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            note(format!(
+                "{}: {}",
+                t!("config.create_failed", path = dir.display()),
+                err
+            ));
+        }
+        dir
+    })
+}
+
+/// 取出还没有显示的提示
+pub fn take_notes() -> Vec<String> {
+    std::mem::take(&mut *NOTES.lock().unwrap())
+}
+
+/// 记录一条提示，例如迁移失败，由 `take_notes` 取出后显示
+pub fn note(msg: String) {
+    NOTES.lock().unwrap().push(msg);
+}
+
+fn platform_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(K_APP_DIR))
+}
+
+/// 配置目录下的文件
+pub fn path(name: &str) -> PathBuf {
+    dir().join(name)
+}
+
+/// 账号的缓存目录，不存在时创建
+pub fn account_dir(name: &str) -> PathBuf {
+    let accounts = dir().join(K_ACCOUNTS_DIR);
+    let dir = accounts.join(account_dir_name(name));
+
+    // 旧版本的目录名只替换了特殊字符，移动到新的目录
+    let legacy = accounts.join(sanitize(name));
+    if !dir.exists() && legacy.is_dir() {
+        if let Err(err) = std::fs::rename(&legacy, &dir) {
+            note(t!(
+                "config.migrate_failed",
+                path = legacy.display(),
+                error = err
+            ));
+        }
+    }
+
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        note(t!(
+            "config.account_dir_failed",
+            path = dir.display(),
+            error = err
+        ));
+    }
+    dir
+}
+
+/// 账号名来自用户输入，只保留可以安全用作目录名的字符，再加上账号名的 hash，
+/// 避免 "a.b" 和 "a_b"、或者在不区分大小写的文件系统上 "A" 和 "a" 使用同一个目录
+fn account_dir_name(name: &str) -> String {
+    let hash: String = Sha256::digest(name.as_bytes())[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}-{}", sanitize(name), hash)
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn exe_dir() -> anyhow::Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    exe.parent()
+        .map(Path::to_path_buf)
+        .context(t!("config.no_exe_dir"))
+}
+
+fn portable_marker_exists() -> bool {
+    exe_dir().is_ok_and(|dir| dir.join(K_PORTABLE_MARKER).exists())
+}
+
+/// 旧版本把文件保存在当前工作目录，第一次运行时移动到配置目录
+fn migrate(dir: &Path) {
+    for name in [
+        cache::K_CACHE_FILE,
+        dedup::K_DEDUP_FILE,
+        queue::K_QUEUE_FILE,
+    ] {
+        let legacy = Path::new(".").join(name);
+        let target = dir.join(name);
+        if !legacy.is_file() || target.exists() {
+            continue;
+        }
+
+        // 复制后再删除，支持跨文件系统
+        let res = std::fs::rename(&legacy, &target).or_else(|_| {
+            std::fs::copy(&legacy, &target)?;
+            std::fs::remove_file(&legacy)
+        });
+        match res {
+            Ok(_) => note(t!(
+                "config.migrated",
+                from = legacy.display(),
+                to = target.display()
+            )),
+            Err(err) => note(t!(
+                "config.migrate_failed",
+                path = legacy.display(),
+                error = err
+            )),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::{Arc, OnceLock};
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use serde::{Deserialize, Serialize};
+
+use crate::i18n::t;
+
+/// 上传前转换的目标格式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    /// sm.ms 支持的格式保持不变，其它格式转换为 PNG
+    #[default]
+    Auto,
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Auto,
+        OutputFormat::Png,
+        OutputFormat::Jpeg,
+        OutputFormat::WebP,
+    ];
+
+    pub fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            OutputFormat::Auto => None,
+            OutputFormat::Png => Some(ImageFormat::Png),
+            OutputFormat::Jpeg => Some(ImageFormat::Jpeg),
+            OutputFormat::WebP => Some(ImageFormat::WebP),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OutputFormat::Auto => t!("format.auto"),
+            OutputFormat::Png => "PNG",
+            OutputFormat::Jpeg => "JPEG",
+            OutputFormat::WebP => "WebP",
+        }
+    }
+}
+
+/// 根据文件内容判断是否为 SVG
+pub fn is_svg(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<svg") || head.starts_with("<!"))
+        && head.contains("<svg")
+}
+
+/// SVG 的原始尺寸
+pub fn svg_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default()).ok()?;
+    let size = tree.size().to_int_size();
+    Some((size.width(), size.height()))
+}
+
+/// 按 `scale` 倍将 SVG 渲染为位图
+pub fn rasterize_svg(bytes: &[u8], scale: f32) -> anyhow::Result<DynamicImage> {
+    let options = usvg::Options {
+        fontdb: system_fonts(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_data(bytes, &options)?;
+
+    let Some(size) = tree.size().to_int_size().scale_by(scale) else {
+        anyhow::bail!(t!("convert.invalid_scale", scale = scale));
+    };
+    let Some(mut pixmap) = tiny_skia::Pixmap::new(size.width(), size.height()) else {
+        anyhow::bail!(t!(
+            "convert.invalid_svg_size",
+            width = size.width(),
+            height = size.height()
+        ));
+    };
+
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia 使用预乘 alpha，需要还原
+    let data = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+
+    let Some(img) = RgbaImage::from_raw(size.width(), size.height(), data) else {
+        anyhow::bail!(t!("convert.svg_failed"));
+    };
+
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+/// 渲染 SVG 中的文字需要系统字体，只加载一次
+fn system_fonts() -> Arc<usvg::fontdb::Database> {
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+
+    FONTS
+        .get_or_init(|| {
+            let mut db = usvg::fontdb::Database::new();
+            db.load_system_fonts();
+            Arc::new(db)
+        })
+        .clone()
+}
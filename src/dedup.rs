@@ -0,0 +1,125 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{api, config, i18n::t, util};
+
+/// 保存在账号目录中，旧版本保存在配置目录中
+pub const K_DEDUP_FILE: &str = "sm_ms_dedup.json";
+
+pub type SharedDedupIndex = Arc<Mutex<DedupIndex>>;
+
+/// 多个上传任务同时保存时按顺序写入文件
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 已上传过的文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupEntry {
+    /// 上传时的本地路径
+    pub path: String,
+    /// sm.ms 返回的图片 hash，删除图片时使用
+    pub hash: String,
+    pub url: String,
+}
+
+/// 以文件内容的 sha256 为 key 的本地上传记录，用来避免重复上传
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DedupIndex {
+    entries: HashMap<String, DedupEntry>,
+    /// 没有登录账号时不保存
+    #[serde(skip)]
+    file: Option<PathBuf>,
+}
+
+impl DedupIndex {
+    /// 读取账号的本地记录，文件不存在或者损坏时返回空记录
+    pub fn load(account: Option<&str>) -> Self {
+        let Some(file) = account.map(|name| config::account_dir(name).join(K_DEDUP_FILE)) else {
+            return Self::default();
+        };
+        let mut index: Self = std::fs::read(&file)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        index.file = Some(file);
+        index
+    }
+
+    /// 只在序列化时持有 `index` 的锁，写文件时不会阻塞其他任务；在异步任务中使用 `spawn_blocking` 调用
+    ///
+    /// 写入时才读取记录，后写入的总是最新的记录
+    pub fn save(index: &SharedDedupIndex) -> anyhow::Result<()> {
+        let _lock = SAVE_LOCK.lock().unwrap();
+        let (file, bytes) = {
+            let index = index.lock().unwrap();
+            let Some(file) = index.file.clone() else {
+                return Ok(());
+            };
+            (file, serde_json::to_vec(&*index)?)
+        };
+        Ok(util::write_atomic(&file, &bytes)?)
+    }
+
+    pub fn shared(account: Option<&str>) -> SharedDedupIndex {
+        Arc::new(Mutex::new(Self::load(account)))
+    }
+
+    /// 旧版本的记录不区分账号，移动到迁移后的账号目录
+    pub fn migrate_legacy(account: &str) {
+        let legacy = config::path(K_DEDUP_FILE);
+        let target = config::account_dir(account).join(K_DEDUP_FILE);
+        if legacy.is_file() && !target.exists() {
+            if let Err(err) = std::fs::rename(&legacy, &target) {
+                config::note(t!(
+                    "config.migrate_failed",
+                    path = legacy.display(),
+                    error = err
+                ));
+            }
+        }
+    }
+
+    pub fn content_hash(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn get(&self, content_hash: &str) -> Option<&DedupEntry> {
+        self.entries.get(content_hash)
+    }
+
+    pub fn insert(&mut self, content_hash: String, entry: DedupEntry) {
+        self.entries.insert(content_hash, entry);
+    }
+
+    /// sm.ms 的图片 hash 到上传时本地路径的映射
+    pub fn local_paths(&self) -> HashMap<String, String> {
+        self.entries
+            .values()
+            .map(|entry| (entry.hash.clone(), entry.path.clone()))
+            .collect()
+    }
+
+    /// 图片在 sm.ms 上被删除后移除对应记录
+    pub fn remove_hash(&mut self, hash: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|_, entry| entry.hash != hash);
+        self.entries.len() != len
+    }
+
+    /// 和上传历史对比，移除已经不在 sm.ms 上的记录，返回移除的数量
+    pub fn reconcile(&mut self, history: &[api::UploadHistoryData]) -> usize {
+        let remote: HashSet<&str> = history.iter().map(|data| data.hash.as_str()).collect();
+        let len = self.entries.len();
+        self.entries
+            .retain(|_, entry| remote.contains(entry.hash.as_str()));
+        len - self.entries.len()
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use ab_glyph::Font;
+
+use crate::i18n::t;
+
+/// 用来判断字体是否支持中文
+const K_CJK_SAMPLE: char = '中';
+
+/// 只包含界面用到的汉字的子集，使用 data/fonts/subset.py 生成
+#[cfg(feature = "bundled-font")]
+const K_BUNDLED_FONT: &[u8] = include_bytes!("../data/fonts/cjk-subset.otf");
+
+/// 常见的系统中文字体
+#[cfg(target_os = "windows")]
+const K_SYSTEM_FONTS: &[&str] = &["msyh.ttc", "msyh.ttf", "simhei.ttf", "simsun.ttc"];
+
+#[cfg(target_os = "macos")]
+const K_SYSTEM_FONTS: &[&str] = &[
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/STHeiti Light.ttc",
+    "/System/Library/Fonts/Hiragino Sans GB.ttc",
+    "/System/Library/Fonts/Supplemental/Arial Unicode.ttf",
+    "/Library/Fonts/Arial Unicode.ttf",
+];
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const K_SYSTEM_FONTS: &[&str] = &[
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/adobe-source-han-sans/SourceHanSansCN-Regular.otf",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-zenhei.ttc",
+    "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+    "/usr/share/fonts/truetype/droid/DroidSansFallbackFull.ttf",
+];
+
+/// 找到的字体
+pub struct FontSource {
+    /// 在设置中显示，例如字体路径
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// 按顺序查找支持中文的字体: `font_path` > 系统字体 > 内置字体
+///
+/// 无法读取或者无法解析的字体会被跳过，避免 egui 加载字体时 panic
+pub fn find(font_path: &str) -> Option<FontSource> {
+    let font_path = font_path.trim();
+    if !font_path.is_empty() {
+        // 用户指定的字体只要求可以解析
+        match read(Path::new(font_path), false) {
+            Some(font) => return Some(font),
+            None => {
+                dbg!(t!("font.unusable", path = font_path));
+            }
+        }
+    }
+
+    system_fonts()
+        .iter()
+        .find_map(|path| read(path, true))
+        .or_else(bundled)
+}
+
+fn read(path: &Path, require_cjk: bool) -> Option<FontSource> {
+    let data = std::fs::read(path).ok()?;
+    let font = ab_glyph::FontRef::try_from_slice_and_index(&data, 0).ok()?;
+    if require_cjk && font.glyph_id(K_CJK_SAMPLE).0 == 0 {
+        return None;
+    }
+
+    Some(FontSource {
+        name: path.display().to_string(),
+        data,
+    })
+}
+
+/// fontconfig 的结果优先，然后是常见的字体路径
+fn system_fonts() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fontconfig_match().into_iter().collect();
+
+    #[cfg(target_os = "windows")]
+    {
+        let windir = std::env::var("WINDIR").unwrap_or_else(|_| "C:\\Windows".to_owned());
+        let fonts = Path::new(&windir).join("Fonts");
+        paths.extend(K_SYSTEM_FONTS.iter().map(|name| fonts.join(name)));
+    }
+    #[cfg(not(target_os = "windows"))]
+    paths.extend(K_SYSTEM_FONTS.iter().map(PathBuf::from));
+
+    paths
+}
+
+/// 使用 fc-match 查找中文字体，没有安装 fontconfig 时返回 None
+#[cfg(all(unix, not(target_os = "macos")))]
+fn fontconfig_match() -> Option<PathBuf> {
+    let output = std::process::Command::new("fc-match")
+        .args(["--format=%{file}", "sans-serif:lang=zh-cn"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let path = String::from_utf8(output.stdout).ok()?;
+    let path = path.trim();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Windows 和 macOS 上一般没有 fontconfig，只查找常见的字体路径
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn fontconfig_match() -> Option<PathBuf> {
+    None
+}
+
+/// 启用了 bundled-font 时内置的字体子集，build.rs 会检查字体文件是否已经生成
+#[cfg(feature = "bundled-font")]
+fn bundled() -> Option<FontSource> {
+    Some(FontSource {
+        name: t!("font.bundled").to_owned(),
+        data: K_BUNDLED_FONT.to_vec(),
+    })
+}
+
+#[cfg(not(feature = "bundled-font"))]
+fn bundled() -> Option<FontSource> {
+    None
+}
@@ -0,0 +1,344 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{api, config};
+
+/// 保存在账号目录中
+pub const K_HISTORY_DB: &str = "sm_ms_history.db";
+
+/// 其他连接正在写入时等待的时间，例如同步时删除了图片
+const K_BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// 缩略图的最大宽高，和上传历史中显示的宽度一致
+const K_THUMBNAIL_SIZE: u32 = 320;
+
+const K_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS history (
+    hash TEXT PRIMARY KEY,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    filename TEXT NOT NULL,
+    storename TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    path TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    url TEXT NOT NULL,
+    delete_url TEXT NOT NULL,
+    page TEXT NOT NULL,
+    local_path TEXT,
+    thumbnail BLOB,
+    synced_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS history_created_at ON history (created_at);
+";
+
+/// 本地保存的上传记录
+#[derive(Debug, Clone)]
+pub struct HistoryRecord {
+    pub data: api::UploadHistoryData,
+    /// 上传时的本地路径，只有使用本程序上传的图片才有
+    pub local_path: Option<String>,
+    /// 缩略图 png，没有联网时显示
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+/// 同步的结果
+#[derive(Debug, Default)]
+pub struct SyncResult {
+    pub added: usize,
+    pub removed: usize,
+    /// 完整同步时为 sm.ms 上的所有记录，增量同步时为 None
+    pub remote: Option<Vec<api::UploadHistoryData>>,
+}
+
+/// 上传历史的本地数据库，每个账号一个，无法连接 sm.ms 时也可以浏览和搜索
+pub struct HistoryDb {
+    conn: Connection,
+}
+
+impl HistoryDb {
+    /// 没有账号时使用内存数据库
+    pub fn open(account: Option<&str>) -> anyhow::Result<Self> {
+        let conn = match account {
+            Some(name) => Connection::open(config::account_dir(name).join(K_HISTORY_DB))?,
+            None => Connection::open_in_memory()?,
+        };
+        conn.busy_timeout(K_BUSY_TIMEOUT)?;
+        conn.execute_batch(K_SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// 按上传时间倒序
+    pub fn records(&self) -> anyhow::Result<Vec<HistoryRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT width, height, filename, storename, size, path, hash, created_at, url,
+                    delete_url, page, local_path, thumbnail
+             FROM history ORDER BY created_at DESC",
+        )?;
+        let records = stmt
+            .query_map([], |row| {
+                Ok(HistoryRecord {
+                    data: api::UploadHistoryData {
+                        width: row.get(0)?,
+                        height: row.get(1)?,
+                        filename: row.get(2)?,
+                        storename: row.get(3)?,
+                        size: row.get::<_, i64>(4)? as usize,
+                        path: row.get(5)?,
+                        hash: row.get(6)?,
+                        created_at: row.get(7)?,
+                        url: row.get(8)?,
+                        delete: row.get(9)?,
+                        page: row.get(10)?,
+                    },
+                    local_path: row.get(11)?,
+                    thumbnail: row.get(12)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(records)
+    }
+
+    pub fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .conn
+            .query_row("SELECT 1 FROM history WHERE hash = ?1", [hash], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    /// 合并 sm.ms 返回的记录，保留本地字段，返回新增的数量
+    ///
+    /// `local_paths` 为 sm.ms hash 到本地路径的映射
+    pub fn upsert(
+        &mut self,
+        history: &[api::UploadHistoryData],
+        local_paths: &HashMap<String, String>,
+    ) -> anyhow::Result<usize> {
+        let synced_at = time::OffsetDateTime::now_utc().unix_timestamp();
+        let tx = self.conn.transaction()?;
+        let mut added = 0;
+        {
+            let mut exists = tx.prepare("SELECT 1 FROM history WHERE hash = ?1")?;
+            let mut upsert = tx.prepare(
+                "INSERT INTO history (hash, width, height, filename, storename, size, path,
+                                      created_at, url, delete_url, page, local_path, synced_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                 ON CONFLICT (hash) DO UPDATE SET
+                    width = excluded.width, height = excluded.height,
+                    filename = excluded.filename, storename = excluded.storename,
+                    size = excluded.size, path = excluded.path,
+                    created_at = excluded.created_at, url = excluded.url,
+                    delete_url = excluded.delete_url, page = excluded.page,
+                    local_path = COALESCE(history.local_path, excluded.local_path),
+                    synced_at = excluded.synced_at",
+            )?;
+
+            for data in history {
+                if !exists.exists([&data.hash])? {
+                    added += 1;
+                }
+                upsert.execute(params![
+                    data.hash,
+                    data.width,
+                    data.height,
+                    data.filename,
+                    data.storename,
+                    data.size as i64,
+                    data.path,
+                    data.created_at,
+                    data.url,
+                    data.delete,
+                    data.page,
+                    local_paths.get(&data.hash),
+                    synced_at,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// 移除不在 `hashes` 中的记录，返回移除的数量
+    pub fn retain(&mut self, hashes: &HashSet<&str>) -> anyhow::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let local: Vec<String> = tx
+            .prepare("SELECT hash FROM history")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        let mut removed = 0;
+        for hash in local.iter().filter(|hash| !hashes.contains(hash.as_str())) {
+            removed += tx.execute("DELETE FROM history WHERE hash = ?1", [hash])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// 图片在 sm.ms 上被删除后移除对应记录
+    pub fn remove(&self, hash: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute("DELETE FROM history WHERE hash = ?1", [hash])?;
+        Ok(())
+    }
+
+    pub fn set_thumbnail(&self, hash: &str, thumbnail: &[u8]) -> anyhow::Result<()> {
+        self.conn.execute(
+            "UPDATE history SET thumbnail = ?2 WHERE hash = ?1",
+            params![hash, thumbnail],
+        )?;
+        Ok(())
+    }
+
+    /// 从 sm.ms 同步上传历史
+    ///
+    /// 增量同步从最新的一页开始，遇到已经保存的记录就停止翻页；
+    /// 完整同步会读取所有分页，并移除已经在 sm.ms 上删除的记录
+    pub async fn sync(
+        &mut self,
+        token: &str,
+        local_paths: &HashMap<String, String>,
+        full: bool,
+    ) -> anyhow::Result<SyncResult> {
+        let mut history = vec![];
+        let mut page = 1;
+
+        loop {
+            let (data, total_pages) = api::upload_history_page(token, page).await?;
+            let mut known = false;
+            for d in &data {
+                known |= self.contains(&d.hash)?;
+            }
+            history.extend(data);
+
+            if (known && !full) || page >= total_pages {
+                break;
+            }
+            page += 1;
+        }
+
+        let mut res = SyncResult {
+            added: self.upsert(&history, local_paths)?,
+            ..Default::default()
+        };
+        if full {
+            let hashes = history.iter().map(|data| data.hash.as_str()).collect();
+            res.removed = self.retain(&hashes)?;
+            res.remote = Some(history);
+        }
+        Ok(res)
+    }
+}
+
+/// 生成 png 缩略图
+pub fn thumbnail(img: &image::DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = std::io::Cursor::new(vec![]);
+    img.thumbnail(K_THUMBNAIL_SIZE, K_THUMBNAIL_SIZE)
+        .write_to(&mut bytes, image::ImageOutputFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+/// 按文件名、url、上传时间和本地路径搜索，不区分大小写
+pub fn matches(data: &api::UploadHistoryData, local_path: Option<&str>, query: &str) -> bool {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return true;
+    }
+
+    [
+        data.filename.as_str(),
+        data.storename.as_str(),
+        data.url.as_str(),
+        data.created_at.as_str(),
+        local_path.unwrap_or_default(),
+    ]
+    .iter()
+    .any(|field| field.to_lowercase().contains(&query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(hash: &str, created_at: &str) -> api::UploadHistoryData {
+        api::UploadHistoryData {
+            width: 1,
+            height: 1,
+            filename: format!("{}.png", hash),
+            storename: format!("{}.png", hash),
+            size: 1,
+            path: format!("/{}.png", hash),
+            hash: hash.to_owned(),
+            created_at: created_at.to_owned(),
+            url: format!("https://i.loli.net/{}.png", hash),
+            delete: format!("https://sm.ms/delete/{}", hash),
+            page: format!("https://sm.ms/image/{}", hash),
+        }
+    }
+
+    fn local_paths(paths: &[(&str, &str)]) -> HashMap<String, String> {
+        paths
+            .iter()
+            .map(|(hash, path)| (hash.to_string(), path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn upsert_keeps_local_path() {
+        let mut db = HistoryDb::open(None).unwrap();
+        let history = [
+            data("a", "2023-01-01 00:00:00"),
+            data("b", "2023-01-02 00:00:00"),
+        ];
+        let added = db
+            .upsert(&history, &local_paths(&[("a", "/home/a.png")]))
+            .unwrap();
+        assert_eq!(added, 2);
+
+        // 再次同步时没有本地路径，也不能覆盖已经保存的
+        let mut renamed = data("a", "2023-01-01 00:00:00");
+        renamed.filename = "renamed.png".to_owned();
+        let added = db
+            .upsert(&[renamed], &local_paths(&[("a", "/other/a.png")]))
+            .unwrap();
+        assert_eq!(added, 0);
+        let added = db.upsert(&history[..1], &HashMap::new()).unwrap();
+        assert_eq!(added, 0);
+
+        let records = db.records().unwrap();
+        let hashes: Vec<&str> = records.iter().map(|r| r.data.hash.as_str()).collect();
+        assert_eq!(hashes, ["b", "a"]);
+        assert_eq!(records[1].local_path.as_deref(), Some("/home/a.png"));
+        assert_eq!(records[1].data.filename, "a.png");
+        assert_eq!(records[0].local_path, None);
+    }
+
+    #[test]
+    fn retain_removes_deleted_records() {
+        let mut db = HistoryDb::open(None).unwrap();
+        let history = [data("a", "1"), data("b", "2"), data("c", "3")];
+        db.upsert(&history, &local_paths(&[("a", "/home/a.png")]))
+            .unwrap();
+        db.set_thumbnail("a", b"thumbnail").unwrap();
+
+        let removed = db.retain(&["a", "c"].into_iter().collect()).unwrap();
+        assert_eq!(removed, 1);
+        assert!(!db.contains("b").unwrap());
+
+        let records = db.records().unwrap();
+        assert_eq!(records.len(), 2);
+        let a = records.iter().find(|r| r.data.hash == "a").unwrap();
+        assert_eq!(a.local_path.as_deref(), Some("/home/a.png"));
+        assert_eq!(a.thumbnail.as_deref(), Some(&b"thumbnail"[..]));
+    }
+
+    #[test]
+    fn search_matches_local_path() {
+        let data = data("a", "2023-05-01 12:00:00");
+        assert!(matches(&data, Some("/home/Photos/cat.png"), "photos"));
+        assert!(matches(&data, None, "2023-05"));
+        assert!(matches(&data, None, " "));
+        assert!(!matches(&data, None, "dog"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+
+/// 界面语言
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lang {
+    #[default]
+    #[serde(rename = "en")]
+    En,
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::ZhCn];
+
+    /// 使用各自的语言显示，切换到看不懂的语言后也能找到
+    pub fn label(self) -> &'static str {
+        match self {
+            Lang::En => "English",
+            Lang::ZhCn => "简体中文",
+        }
+    }
+
+    /// 系统语言为中文时使用简体中文，其他语言使用英文
+    pub fn detect() -> Self {
+        let locale = sys_locale::get_locale().unwrap_or_default();
+        if locale.to_lowercase().starts_with("zh") {
+            Lang::ZhCn
+        } else {
+            Lang::En
+        }
+    }
+
+    fn catalog(self) -> &'static str {
+        match self {
+            Lang::En => include_str!("../data/i18n/en.json"),
+            Lang::ZhCn => include_str!("../data/i18n/zh-CN.json"),
+        }
+    }
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+static CATALOGS: OnceLock<Vec<HashMap<String, String>>> = OnceLock::new();
+
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn lang() -> Lang {
+    Lang::ALL[LANG.load(Ordering::Relaxed) as usize]
+}
+
+fn catalogs() -> &'static [HashMap<String, String>] {
+    CATALOGS.get_or_init(|| {
+        Lang::ALL
+            .iter()
+            .map(|lang| serde_json::from_str(lang.catalog()).expect("无效的语言文件"))
+            .collect()
+    })
+}
+
+/// 当前语言中的文本，没有时使用英文
+pub fn lookup(key: &str) -> Option<&'static str> {
+    let catalogs = catalogs();
+    catalogs[lang() as usize]
+        .get(key)
+        .or_else(|| catalogs[Lang::En as usize].get(key))
+        .map(String::as_str)
+}
+
+/// 语言文件中没有的 key 原样显示，方便发现遗漏
+pub fn text(key: &'static str) -> &'static str {
+    lookup(key).unwrap_or(key)
+}
+
+/// 替换文本中的 `{name}`
+pub fn format(text: &str, args: &[(&str, String)]) -> String {
+    args.iter().fold(text.to_owned(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// 当前语言的文本，例如 `t!("login.submit")`、`t!("scan.found", count = 3)`
+macro_rules! t {
+    ($key:expr) => {
+        $crate::i18n::text($key)
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::format(
+            $crate::i18n::text($key),
+            &[$((stringify!($name), $value.to_string())),+],
+        )
+    };
+}
+
+pub(crate) use t;
@@ -1,4 +1,5 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+use clap::Parser;
 use eframe::egui::output::OpenUrl;
 use eframe::egui::{Hyperlink, Ui};
 use eframe::{
@@ -7,23 +8,80 @@ use eframe::{
 };
 use egui_extras::RetainedImage;
 use poll_promise::Promise;
+use std::sync::Arc;
 use tokio::runtime::Runtime;
 
+use crate::i18n::t;
+
 mod api;
+mod archive;
 mod cache;
+mod cli;
+mod config;
+mod convert;
+mod dedup;
+mod font;
+mod history;
+mod i18n;
+mod metadata;
+mod mime;
+mod phash;
+mod preflight;
+mod preprocess;
+mod profile;
+mod queue;
+mod remote;
+mod rename;
+mod scan;
+mod secret;
 mod util;
+mod watermark;
 mod widget;
 
 const K_IMAGE_MAX_WIDTH: f32 = 320.0;
-const K_TABS: [&str; 3] = ["Upload History", "Now Upload", "Profile"];
+const K_PREVIEW_MAX_WIDTH: f32 = 160.0;
+/// 同时下载的上传历史图片数量，下载后只保存缩略图
+const K_THUMBNAIL_DOWNLOADS: usize = 4;
+/// 停止编辑上传路径后等待的秒数，之后才读取预览
+const K_PREVIEW_DEBOUNCE: f64 = 0.5;
+const K_TABS: [&str; 4] = ["tab.history", "tab.upload", "tab.profile", "tab.similar"];
 
 fn main() -> Result<(), eframe::Error> {
+    // 必须在启动 runtime 之前读取
+    util::init_local_offset();
+    attach_console();
     env_logger::init();
-    let mut options = eframe::NativeOptions::default();
-    options.icon_data = Some(util::load_app_icon());
 
-    // options.initial_window_pos = Some([0f32, 0f32].into());
-    options.min_window_size = Some([600f32, 400f32].into());
+    // 带子命令时以命令行模式运行
+    let cli = cli::Cli::parse();
+    if let Err(err) = config::init(cli.config_dir, cli.portable) {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+
+    // 命令行模式也使用设置的语言
+    let language = cache::SmMsCacheData::from()
+        .ok()
+        .and_then(|cache_data| cache_data.language);
+    i18n::set_lang(language.unwrap_or_else(i18n::Lang::detect));
+
+    if let Some(command) = cli.command {
+        for note in config::take_notes() {
+            eprintln!("{}", note);
+        }
+        if let Err(err) = cli::run(cli.token, cli.account, command) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let options = eframe::NativeOptions {
+        icon_data: Some(util::load_app_icon()),
+        // initial_window_pos: Some([0f32, 0f32].into()),
+        min_window_size: Some([600f32, 400f32].into()),
+        ..Default::default()
+    };
 
     eframe::run_native(
         "sm ms",
@@ -32,45 +90,265 @@ fn main() -> Result<(), eframe::Error> {
     )
 }
 
+/// 发布版本在 Windows 上是图形界面程序，没有自己的控制台，连接到启动它的命令行才能看到输出
+#[cfg(all(windows, not(debug_assertions)))]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // 从资源管理器启动时没有父控制台，连接失败可以忽略
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(all(windows, not(debug_assertions))))]
+fn attach_console() {}
+
 /* #region UploadHistoryDataUi */
+/// 本地数据库中的上传历史
+struct UploadHistoryUi {
+    items: Vec<UploadHistoryDataUi>,
+    /// 同步结果或者无法连接 sm.ms 时的错误
+    sync_msg: String,
+    sync_ok: bool,
+}
+
 struct UploadHistoryDataUi {
     data: api::UploadHistoryData,
+    /// 上传时的本地路径
+    local_path: Option<String>,
     image_promise: Promise<Result<RetainedImage, String>>,
+    phash_promise: Promise<Option<phash::PerceptualHash>>,
 }
 
 impl UploadHistoryDataUi {
-    fn from_data(data: api::UploadHistoryData, ctx: egui::Context) -> Self {
+    /// 有缩略图时不需要联网，否则下载后保存缩略图，只在内存中保留缩略图
+    ///
+    /// 需要在 runtime 中调用，`downloads` 限制同时下载的数量，下载后的缩略图发送到 `thumbnails` 保存
+    fn from_record(
+        record: history::HistoryRecord,
+        downloads: Arc<tokio::sync::Semaphore>,
+        thumbnails: tokio::sync::mpsc::UnboundedSender<(String, Vec<u8>)>,
+        ctx: egui::Context,
+    ) -> Self {
+        let history::HistoryRecord {
+            data,
+            local_path,
+            thumbnail,
+        } = record;
+
+        if let Some(thumbnail) = thumbnail {
+            let image = RetainedImage::from_image_bytes(&data.url, &thumbnail);
+            let phash = phash::PerceptualHash::from_bytes(&thumbnail);
+            return UploadHistoryDataUi {
+                data,
+                local_path,
+                image_promise: Promise::from_ready(image),
+                phash_promise: Promise::from_ready(phash),
+            };
+        }
+
         let (sender, promise) = Promise::new();
-        let request = ehttp::Request::get(&data.url);
-        ehttp::fetch(request, move |response| {
-            let image = response.and_then(util::parse_ehttp_response);
+        let (phash_sender, phash_promise) = Promise::new();
+        let url = data.url.clone();
+        let hash = data.hash.clone();
+        tokio::spawn(async move {
+            let thumbnail = async {
+                let _permit = downloads.acquire_owned().await?;
+                let (bytes, _) = remote::download(&url).await?;
+                // sm.ms 上的是原图，解码和缩小比较耗时
+                tokio::task::spawn_blocking(move || {
+                    history::thumbnail(&image::load_from_memory(&bytes)?)
+                })
+                .await?
+            }
+            .await;
+
+            // 相似图片使用缩略图计算，和没有联网时一致
+            let phash = thumbnail
+                .as_ref()
+                .ok()
+                .and_then(|thumbnail| phash::PerceptualHash::from_bytes(thumbnail));
+            phash_sender.send(phash);
+
+            if let Ok(thumbnail) = &thumbnail {
+                // 没有保存时下次加载会重新下载
+                let _ = thumbnails.send((hash, thumbnail.clone()));
+            }
+
+            let image = thumbnail
+                .map_err(|err| format!("{:#}", err))
+                .and_then(|thumbnail| RetainedImage::from_image_bytes(&url, &thumbnail));
             sender.send(image);
             ctx.request_repaint();
         });
 
         UploadHistoryDataUi {
             data,
+            local_path,
             image_promise: promise,
+            phash_promise,
+        }
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        history::matches(&self.data, self.local_path.as_deref(), query)
+    }
+
+    fn phash(&self) -> Option<&phash::PerceptualHash> {
+        self.phash_promise.ready().and_then(Option::as_ref)
+    }
+
+    fn show_thumbnail(&self, ui: &mut Ui, max_width: f32) {
+        if let Some(Ok(image)) = self.image_promise.ready() {
+            let w = image.width() as f32;
+            let h = image.height() as f32;
+            let scale = (max_width / w).min(1.0);
+            image.show_size(ui, [w * scale, h * scale].into());
+        } else {
+            ui.spinner();
+        }
+    }
+}
+/* #endregion */
+
+/* #region UploadPreviewUi */
+/// 一个待上传的路径，在后台读取和校验，压缩包会展开成多个预览
+struct UploadSourceUi {
+    path: String,
+    previews: Promise<Vec<UploadPreviewUi>>,
+}
+
+impl UploadSourceUi {
+    fn load(
+        path: String,
+        options: preprocess::PreprocessOptions,
+        rt: &Runtime,
+        ctx: &egui::Context,
+    ) -> Self {
+        let (sender, previews) = Promise::new();
+        let source = path.clone();
+        let ctx = ctx.clone();
+        rt.spawn_blocking(move || {
+            sender.send(UploadPreviewUi::from_source(&source, &options));
+            ctx.request_repaint();
+        });
+        Self { path, previews }
+    }
+}
+
+struct UploadPreviewUi {
+    preview: preflight::UploadPreview,
+    image: Option<RetainedImage>,
+    phash: Option<phash::PerceptualHash>,
+}
+
+impl UploadPreviewUi {
+    /// 压缩包会展开成多个预览
+    fn from_source(path: &str, options: &preprocess::PreprocessOptions) -> Vec<Self> {
+        preflight::UploadPreview::read_source(path, options, Self::new)
+    }
+
+    fn new(preview: preflight::UploadPreview, bytes: Option<Vec<u8>>) -> Self {
+        // 只有通过校验的文件才加载缩略图，只保留缩小后的图片
+        let img = bytes
+            .filter(|_| preview.is_ok())
+            .and_then(|bytes| image::load_from_memory(&bytes).ok());
+        let phash = img.as_ref().map(phash::PerceptualHash::from_image);
+        let image = img.map(|img| {
+            let max_width = K_PREVIEW_MAX_WIDTH as u32;
+            let img = if img.width() > max_width {
+                img.thumbnail(max_width, u32::MAX)
+            } else {
+                img
+            };
+            let size = [img.width() as usize, img.height() as usize];
+            let color_image = egui::ColorImage::from_rgba_unmultiplied(size, &img.to_rgba8());
+            RetainedImage::from_color_image(preview.label(), color_image)
+        });
+
+        UploadPreviewUi {
+            preview,
+            image,
+            phash,
         }
     }
 }
 /* #endregion */
 
+/// 登录方式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LoginMode {
+    /// 用户名和密码，通过 api::token 获取 token
+    #[default]
+    Password,
+    /// 直接粘贴 sm.ms 控制台中的 API token，适合没有密码的账号
+    Token,
+}
+
 struct SmMsApp {
-    action_status: String,
+    /// 每行一个本地文件路径
     upload_path: String,
+    /// 远程图片的 url，下载后上传
+    upload_url: String,
+    upload_sources: Vec<UploadSourceUi>,
+    /// 上传使用的文件名，按预览的标签保存，为空时按上传设置命名
+    upload_filenames: std::collections::HashMap<String, String>,
+    /// 最后一次编辑路径的时间，停止输入一段时间后再读取预览
+    upload_path_edited: Option<f64>,
+    /// 最后一次修改上传设置的时间，拖动滑块或者输入时停下来一段时间后再保存和重新读取预览
+    upload_options_edited: Option<f64>,
     uplaod_res_msg: String,
-
+    preprocess_options: preprocess::PreprocessOptions,
+    dedup: dedup::SharedDedupIndex,
+    upload_queue: queue::UploadQueue,
+    upload_concurrency: usize,
+    /// 上一次看到的上传完成数量，变化时刷新上传历史
+    upload_queue_done: usize,
+    /// 登录后继续上次没有完成的上传
+    upload_queue_resumed: bool,
+    manifest_path: String,
+
+    /* #region scan folder */
+    scan_dir: String,
+    scan_include: String,
+    scan_exclude: String,
+    scan_options: scan::ScanOptions,
+    /// 在后台扫描，完成后加入待上传列表
+    scan_promise: Option<Promise<anyhow::Result<scan::ScanResult>>>,
+    scan_progress: Arc<scan::ScanProgress>,
+    /* #endregion */
     delete_image_model_open: bool,
     delete_img_hash: Option<String>,
+    /// 迁移配置等操作的提示，关闭窗口后清空
+    config_notes: Vec<String>,
+    /// 等待确认移除的账号
+    remove_account_name: Option<String>,
 
     /* #region login */
+    login_mode: LoginMode,
+    /// 使用 API token 登录时为账号名，可以留空
     username: String,
     password: String,
+    api_token: String,
     login_loading: bool,
     login_err: Option<String>,
     token: String,
     token_promise: Option<Promise<anyhow::Result<String>>>,
+    /// 登录时用来加密 token 的密码，为空时使用本机密钥
+    login_passphrase: String,
+    /// 当前使用的账号名，上传记录按账号保存
+    account: Option<String>,
+    /// 账号菜单中显示的账号，打开菜单、登录和退出登录时重新读取
+    accounts: Vec<cache::Account>,
+    /* #endregion */
+
+    /* #region unlock */
+    /// 使用密码加密、等待解锁的 token
+    locked_token: Option<secret::EncryptedToken>,
+    /// 派生密钥比较耗时，在后台解密，同时保存正在解密的 token
+    unlock_promise: Option<Promise<(secret::EncryptedToken, anyhow::Result<String>)>>,
+    unlock_passphrase: String,
+    unlock_err: Option<String>,
     /* #endregion */
     tab_index: usize,
 
@@ -78,8 +356,20 @@ struct SmMsApp {
     profile_promise: Option<Promise<anyhow::Result<api::ProfileData>>>,
     /* #endregion */
 
+    /* #region settings */
+    /// 界面字体路径，为空时自动查找
+    font_path: String,
+    /// 正在使用的字体
+    font_name: String,
+    /// 界面语言，为 None 时跟随系统
+    language: Option<i18n::Lang>,
+    /* #endregion */
+
     /* #region upload history */
-    upload_history_promise: Option<Promise<anyhow::Result<Vec<UploadHistoryDataUi>>>>,
+    upload_history_promise: Option<Promise<anyhow::Result<UploadHistoryUi>>>,
+    /// 下次同步时读取所有分页
+    upload_history_full_sync: bool,
+    history_search: String,
     /* #endregion */
     rt: Runtime,
 }
@@ -88,23 +378,56 @@ impl Default for SmMsApp {
     fn default() -> Self {
         Self {
             upload_path: Default::default(),
+            upload_url: Default::default(),
+            upload_sources: Default::default(),
+            upload_filenames: Default::default(),
+            upload_path_edited: Default::default(),
+            upload_options_edited: Default::default(),
             uplaod_res_msg: Default::default(),
+            preprocess_options: Default::default(),
+            dedup: dedup::DedupIndex::shared(None),
+            upload_queue: queue::UploadQueue::load(),
+            upload_concurrency: queue::K_DEFAULT_CONCURRENCY,
+            upload_queue_done: Default::default(),
+            upload_queue_resumed: Default::default(),
+            manifest_path: "./sm_ms_manifest.json".to_owned(),
+            scan_dir: Default::default(),
+            scan_include: Default::default(),
+            scan_exclude: Default::default(),
+            scan_promise: Default::default(),
+            scan_progress: Default::default(),
+            scan_options: Default::default(),
             delete_image_model_open: Default::default(),
             delete_img_hash: Default::default(),
+            config_notes: Default::default(),
+            remove_account_name: Default::default(),
+            login_mode: Default::default(),
             username: Default::default(),
             password: Default::default(),
+            api_token: Default::default(),
             login_loading: Default::default(),
             login_err: Default::default(),
             token: Default::default(),
             token_promise: Default::default(),
+            login_passphrase: Default::default(),
+            account: Default::default(),
+            accounts: Default::default(),
+            locked_token: Default::default(),
+            unlock_promise: Default::default(),
+            unlock_passphrase: Default::default(),
+            unlock_err: Default::default(),
             tab_index: Default::default(),
             profile_promise: Default::default(),
+            font_path: Default::default(),
+            font_name: Default::default(),
+            language: Default::default(),
             upload_history_promise: Default::default(),
+            upload_history_full_sync: Default::default(),
+            history_search: Default::default(),
             rt: tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
                 .build()
                 .unwrap(),
-            action_status: Default::default(),
         }
     }
 }
@@ -112,53 +435,414 @@ impl Default for SmMsApp {
 /* #region MyApp constructor */
 impl SmMsApp {
     fn new(cc: &eframe::CreationContext<'_>, cache_data: Option<cache::SmMsCacheData>) -> Self {
-        util::setup_custom_fonts(&cc.egui_ctx);
         let mut my = Self::default();
 
+        if let Some(cache_data) = &cache_data {
+            my.font_path = cache_data.font_path.clone();
+            my.language = cache_data.language;
+        }
+        my.font_name = util::setup_custom_fonts(&cc.egui_ctx, &my.font_path);
+
         if let Some(cache_data) = cache_data {
-            // 从缓存中初始化token
-            if let Some(token) = cache_data.token {
-                my.token = token.clone();
-                let (s, p) = Promise::new();
-                my.token_promise = Some(p);
-                s.send(Ok(token.clone()));
+            my.preprocess_options = cache_data.preprocess_for(cache_data.account());
+            if let Some(concurrency) = cache_data.upload_concurrency {
+                my.upload_concurrency = concurrency;
             }
+
+            if let Some(account) = cache_data.account() {
+                my.use_account(account, &cc.egui_ctx);
+            }
+            my.accounts = cache_data.accounts;
         }
 
         my
     }
+
+    /// 切换到保存的账号，使用密码加密并且没有通过环境变量提供密码时先显示解锁界面
+    fn use_account(&mut self, account: &cache::Account, ctx: &egui::Context) {
+        self.set_account(Some(account.name.clone()));
+        self.token.clear();
+        self.token_promise = None;
+        self.login_err = None;
+
+        let encrypted = account.encrypted_token.clone();
+        let passphrase = secret::env_passphrase();
+        if encrypted.needs_passphrase() && passphrase.is_none() {
+            self.locked_token = Some(encrypted);
+        } else {
+            self.decrypt_token(encrypted, passphrase, ctx);
+        }
+    }
+
+    /// 在后台解密 token，完成后由 `poll_unlock` 处理
+    fn decrypt_token(
+        &mut self,
+        encrypted: secret::EncryptedToken,
+        passphrase: Option<String>,
+        ctx: &egui::Context,
+    ) {
+        let (sender, promise) = Promise::new();
+        let ctx = ctx.clone();
+        self.rt.spawn_blocking(move || {
+            let res = encrypted.decrypt(passphrase.as_deref());
+            sender.send((encrypted, res));
+            ctx.request_repaint();
+        });
+        self.unlock_promise = Some(promise);
+    }
+
+    /// 解密失败时，使用密码加密的 token 回到解锁界面，否则显示在登录界面
+    fn poll_unlock(&mut self) {
+        let Some(promise) = self.unlock_promise.take() else {
+            return;
+        };
+        let (encrypted, res) = match promise.try_take() {
+            Ok(res) => res,
+            Err(promise) => {
+                self.unlock_promise = Some(promise);
+                return;
+            }
+        };
+
+        match res {
+            Ok(token) => {
+                self.locked_token = None;
+                self.unlock_passphrase.clear();
+                self.unlock_err = None;
+                self.set_token(token);
+            }
+            Err(err) if encrypted.needs_passphrase() => {
+                self.locked_token = Some(encrypted);
+                self.unlock_err = Some(err.to_string());
+            }
+            Err(err) => self.login_err = Some(err.to_string()),
+        }
+    }
+
+    /// 集中检查各个请求的结果，token 失效时回到登录界面
+    fn check_session(&mut self) {
+        // 每帧都读取，避免重新登录后处理之前的失效
+        let queue_expired = self.upload_queue.take_expired(self.account.as_deref());
+
+        let logged_in = matches!(
+            self.token_promise.as_ref().and_then(|p| p.ready()),
+            Some(Ok(_))
+        );
+        if !logged_in {
+            return;
+        }
+
+        let err = [
+            self.profile_promise
+                .as_ref()
+                .and_then(|p| p.ready())
+                .and_then(|res| res.as_ref().err()),
+            self.upload_history_promise
+                .as_ref()
+                .and_then(|p| p.ready())
+                .and_then(|res| res.as_ref().err()),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|err| api::is_unauthorized(err))
+        .map(|err| err.to_string());
+
+        if let Some(err) = err {
+            self.expire_session(err);
+        } else if queue_expired {
+            self.expire_session(t!("session.expired").to_owned());
+        }
+    }
+
+    /// token 失效后回到登录界面，保留上传队列和当前页面
+    fn expire_session(&mut self, msg: String) {
+        self.token.clear();
+        self.token_promise = None;
+        self.login_err = Some(msg);
+        self.profile_promise = None;
+        self.upload_history_promise = None;
+        self.delete_image_model_open = false;
+        // 重新登录后继续上传等待中的文件
+        self.upload_queue_resumed = false;
+        // 默认重新登录同一个账号
+        if let Some(account) = &self.account {
+            self.username = account.clone();
+        }
+    }
+
+    /// 重置和账号相关的状态，其他账号的上传继续进行
+    fn set_account(&mut self, account: Option<String>) {
+        // 还没有保存的上传设置属于之前的账号
+        if self.upload_options_edited.take().is_some() {
+            self.save_upload_settings();
+        }
+        self.dedup = dedup::DedupIndex::shared(account.as_deref());
+        // 水印按账号保存
+        match cache::SmMsCacheData::load() {
+            Ok(cache_data) => {
+                let account = account
+                    .as_deref()
+                    .and_then(|name| cache_data.find_account(name));
+                self.preprocess_options.watermark = cache_data.preprocess_for(account).watermark;
+            }
+            Err(err) => {
+                dbg!(err);
+            }
+        }
+        // 登录后继续上传这个账号等待中的文件
+        self.upload_queue_resumed = false;
+        self.account = account;
+        self.locked_token = None;
+        self.unlock_promise = None;
+        self.unlock_passphrase.clear();
+        self.unlock_err = None;
+        self.profile_promise = None;
+        self.upload_history_promise = None;
+        self.delete_image_model_open = false;
+        self.delete_img_hash = None;
+    }
+
+    fn reload_accounts(&mut self) {
+        match cache::SmMsCacheData::load() {
+            Ok(cache_data) => self.accounts = cache_data.accounts,
+            Err(err) => {
+                dbg!(err);
+            }
+        }
+    }
+
+    fn set_token(&mut self, token: String) {
+        self.token = token.clone();
+        let (s, p) = Promise::new();
+        self.token_promise = Some(p);
+        s.send(Ok(token));
+    }
 }
 /* #endregion */
 
 /* #region MyApp methods */
 impl SmMsApp {
-    fn upload(&mut self) {
+    fn upload_paths(&self) -> Vec<String> {
+        self.upload_path
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// 在后台读取新加入的路径的预览和校验结果，已经读取过的路径不会重新读取
+    fn refresh_upload_previews(&mut self, ctx: &egui::Context) {
+        self.upload_path_edited = None;
+        let mut sources = std::mem::take(&mut self.upload_sources);
+        self.upload_sources = self
+            .upload_paths()
+            .into_iter()
+            .map(|path| match sources.iter().position(|s| s.path == path) {
+                Some(i) => sources.swap_remove(i),
+                None => UploadSourceUi::load(path, self.preprocess_options.clone(), &self.rt, ctx),
+            })
+            .collect();
+    }
+
+    /// 上传设置会影响校验结果，修改后重新读取所有预览
+    fn reload_upload_previews(&mut self, ctx: &egui::Context) {
+        self.upload_sources.clear();
+        self.refresh_upload_previews(ctx);
+    }
+
+    /// 所有预览，还有预览没有读取完成时返回 None
+    fn upload_previews(&self) -> Option<Vec<&UploadPreviewUi>> {
+        let mut previews = vec![];
+        for source in &self.upload_sources {
+            previews.extend(source.previews.ready()?);
+        }
+        Some(previews)
+    }
+
+    /// 保存上传设置
+    fn save_upload_settings(&self) {
+        let res = cache::SmMsCacheData::update(|cache_data| {
+            let mut preprocess = self.preprocess_options.clone();
+            // 水印保存在当前账号中
+            let watermark = std::mem::take(&mut preprocess.watermark);
+            if let Some(account) = self
+                .account
+                .as_deref()
+                .and_then(|name| cache_data.find_account_mut(name))
+            {
+                account.watermark = watermark;
+            }
+            cache_data.preprocess = preprocess;
+            cache_data.upload_concurrency = Some(self.upload_concurrency);
+            Ok(())
+        });
+        if let Err(err) = res {
+            dbg!(err);
+        }
+    }
+
+    /// 在后台扫描文件夹，完成后把找到的文件加入待上传列表，上传前可以先预览
+    fn scan_folder(&mut self, ctx: &egui::Context) {
+        self.uplaod_res_msg.clear();
+        self.scan_options.include = scan::parse_patterns(&self.scan_include);
+        self.scan_options.exclude = scan::parse_patterns(&self.scan_exclude);
+
+        let (sender, promise) = Promise::new();
+        let root = std::path::PathBuf::from(&self.scan_dir);
+        let options = self.scan_options.clone();
+        let progress = Arc::new(scan::ScanProgress::default());
+        self.scan_progress = progress.clone();
+        let ctx = ctx.clone();
+        self.rt.spawn_blocking(move || {
+            sender.send(scan::scan(&root, &options, &progress));
+            ctx.request_repaint();
+        });
+        self.scan_promise = Some(promise);
+    }
+
+    /// 扫描完成后加入待上传列表
+    fn poll_scan_folder(&mut self, ctx: &egui::Context) {
+        let Some(promise) = self.scan_promise.take() else {
+            return;
+        };
+        let res = match promise.try_take() {
+            Ok(res) => res,
+            Err(promise) => {
+                self.scan_promise = Some(promise);
+                return;
+            }
+        };
+
+        match res {
+            Ok(result) => {
+                let mut paths = self.upload_paths();
+                paths.extend(result.files.iter().map(|file| file.display().to_string()));
+                self.upload_path = paths.join("\n");
+
+                let mut msg = vec![t!("upload.found_files", count = result.files.len())];
+                msg.extend(result.warnings);
+                self.uplaod_res_msg = msg.join("\n");
+                self.refresh_upload_previews(ctx);
+            }
+            Err(err) => self.uplaod_res_msg = format!("{:#}", err),
+        }
+    }
+
+    /// 校验通过后把文件加入上传队列并开始上传
+    fn upload(&mut self, ctx: &egui::Context) {
         dbg!("upload");
         self.uplaod_res_msg.clear();
 
-        if self.upload_path.is_empty() {
-            self.uplaod_res_msg = "请填写上传本地文件路径".to_owned();
+        let Some(previews) = self.upload_previews() else {
+            self.uplaod_res_msg = t!("upload.previews_loading").to_owned();
+            return;
+        };
+        if previews.is_empty() {
+            self.uplaod_res_msg = t!("upload.path_required").to_owned();
+            return;
+        }
+
+        // 在发起网络请求前列出所有问题
+        let problems: Vec<String> = previews
+            .iter()
+            .flat_map(|p| {
+                p.preview
+                    .problems
+                    .iter()
+                    .map(|problem| format!("{}: {}", p.preview.label(), problem))
+            })
+            .collect();
+        if !problems.is_empty() {
+            self.uplaod_res_msg = problems.join("\n");
             return;
         }
 
-        if !std::path::Path::new(&self.upload_path).exists() {
-            self.uplaod_res_msg = "文件不存在".to_owned();
+        for p in previews {
+            let filename = self
+                .upload_filenames
+                .get(&p.preview.label())
+                .map(|filename| filename.trim())
+                .filter(|filename| !filename.is_empty());
+            self.upload_queue.add(
+                p.preview.path.display().to_string(),
+                p.preview.entry.clone(),
+                filename.map(str::to_owned),
+                self.preprocess_options.clone(),
+                self.account.clone(),
+            );
+        }
+        self.upload_path.clear();
+        self.upload_sources.clear();
+        self.upload_filenames.clear();
+
+        self.start_upload_queue(ctx);
+    }
+
+    /// 下载远程图片后上传，下载和校验都在上传队列中进行
+    fn upload_from_url(&mut self, ctx: &egui::Context) {
+        self.uplaod_res_msg.clear();
+        let url = self.upload_url.trim().to_owned();
+        if !remote::is_url(&url) {
+            self.uplaod_res_msg = t!("upload.url_required").to_owned();
             return;
         }
 
-        self.uplaod_res_msg = "上传中...".to_owned();
+        self.upload_queue.add(
+            url,
+            None,
+            None,
+            self.preprocess_options.clone(),
+            self.account.clone(),
+        );
+        self.upload_url.clear();
 
-        let res = self
-            .rt
-            .block_on(async { api::upload(&self.token, &self.upload_path).await });
+        self.start_upload_queue(ctx);
+    }
 
-        match res {
-            Err(err) => self.uplaod_res_msg = err.to_string(),
-            _ => {
-                self.uplaod_res_msg = "上传成功".to_owned();
-                self.upload_history_promise = None;
-            }
+    fn start_upload_queue(&self, ctx: &egui::Context) {
+        let ctx = ctx.clone();
+        self.upload_queue.start(
+            self.rt.handle(),
+            self.account.clone(),
+            self.token.clone(),
+            self.dedup.clone(),
+            self.upload_concurrency,
+            Arc::new(move || ctx.request_repaint()),
+        );
+    }
+
+    /// 上传历史中与 `phash` 相似的图片，按距离排序
+    fn similar_history(&self, phash: &phash::PerceptualHash) -> Vec<(&UploadHistoryDataUi, u32)> {
+        let Some(Ok(history)) = self.upload_history_promise.as_ref().and_then(|p| p.ready()) else {
+            return vec![];
         };
+
+        let mut similar: Vec<_> = history
+            .items
+            .iter()
+            .filter_map(|data| {
+                let distance = data.phash()?.distance(phash);
+                (distance <= phash::K_SIMILAR_THRESHOLD).then_some((data, distance))
+            })
+            .collect();
+        similar.sort_by_key(|(_, distance)| *distance);
+        similar
+    }
+
+    /// 图片删除后移除本地的上传记录
+    fn forget_uploaded(&self, hash: &str) {
+        let removed = self.dedup.lock().unwrap().remove_hash(hash);
+        if removed {
+            if let Err(err) = dedup::DedupIndex::save(&self.dedup) {
+                dbg!(err);
+            }
+        }
+
+        let res = history::HistoryDb::open(self.account.as_deref()).and_then(|db| db.remove(hash));
+        if let Err(err) = res {
+            dbg!(err);
+        }
     }
 
     fn get_profile_data(&mut self, ctx: &egui::Context) {
@@ -167,10 +851,23 @@ impl SmMsApp {
 
             let (sender, promise) = Promise::new();
             let token = self.token.clone();
+            let account = self.account.clone();
             let ctx = ctx.clone();
 
             self.rt.spawn(async move {
                 let res_result = api::profile(&token).await;
+
+                // 保存最后一次获取到的账号信息，在账号菜单中显示
+                if let (Ok(profile), Some(account)) = (&res_result, &account) {
+                    let res = cache::SmMsCacheData::update(|cache_data| {
+                        cache_data.set_profile(account, profile.clone());
+                        Ok(())
+                    });
+                    if let Err(err) = res {
+                        dbg!(err);
+                    }
+                }
+
                 sender.send(res_result);
                 ctx.request_repaint();
             });
@@ -178,26 +875,88 @@ impl SmMsApp {
         });
     }
 
+    /// 从 sm.ms 同步到本地数据库后显示本地记录，无法同步时只显示本地记录
     fn get_upload_history_data(&mut self, ctx: &egui::Context) {
+        let full = std::mem::take(&mut self.upload_history_full_sync);
         self.upload_history_promise.get_or_insert_with(|| {
             dbg!("get_upload_history_data");
 
             let (sender, promise) = Promise::new();
             let ctx = ctx.clone();
             let token = self.token.clone();
+            let account = self.account.clone();
+            let dedup = self.dedup.clone();
             self.rt.spawn(async move {
-                let res_result = api::upload_history(&token).await;
+                let res_result = async {
+                    let mut db = history::HistoryDb::open(account.as_deref())?;
+                    let local_paths = dedup.lock().unwrap().local_paths();
+                    let sync = db.sync(&token, &local_paths, full).await;
+
+                    // 完整同步后移除已经在 sm.ms 上删除的本地记录
+                    if let Ok(history::SyncResult {
+                        remote: Some(history),
+                        ..
+                    }) = &sync
+                    {
+                        let removed = dedup.lock().unwrap().reconcile(history);
+                        if removed > 0 {
+                            let dedup = dedup.clone();
+                            let res = tokio::task::spawn_blocking(move || {
+                                dedup::DedupIndex::save(&dedup)
+                            })
+                            .await?;
+                            if let Err(err) = res {
+                                dbg!(err);
+                            }
+                        }
+                    }
 
-                // Vec<api::UploadHistoryData> to Vec<api::UploadHistoryDataUi>
-                let res_result_ui = res_result.and_then(|o: Vec<api::UploadHistoryData>| {
-                    Ok(o.into_iter()
-                        .map(|upload_history_data| {
-                            UploadHistoryDataUi::from_data(upload_history_data, ctx.clone())
+                    let records = db.records()?;
+                    let (sync_msg, sync_ok) = match sync {
+                        Ok(res) => (
+                            t!("history.synced", added = res.added, removed = res.removed),
+                            true,
+                        ),
+                        // 本地没有记录时没有可以显示的内容，token 失效时需要重新登录
+                        Err(err) if records.is_empty() || api::is_unauthorized(&err) => {
+                            return Err(err)
+                        }
+                        Err(err) => (t!("history.sync_failed", error = err), false),
+                    };
+
+                    let downloads = Arc::new(tokio::sync::Semaphore::new(K_THUMBNAIL_DOWNLOADS));
+                    let (thumbnails, mut downloaded) = tokio::sync::mpsc::unbounded_channel();
+                    let items = records
+                        .into_iter()
+                        .map(|record| {
+                            UploadHistoryDataUi::from_record(
+                                record,
+                                downloads.clone(),
+                                thumbnails.clone(),
+                                ctx.clone(),
+                            )
                         })
-                        .collect())
-                });
+                        .collect();
+                    drop(thumbnails);
+
+                    // 只用同步时的连接写入缩略图，所有下载完成后结束
+                    tokio::task::spawn_blocking(move || {
+                        while let Some((hash, thumbnail)) = downloaded.blocking_recv() {
+                            if let Err(err) = db.set_thumbnail(&hash, &thumbnail) {
+                                dbg!(err);
+                            }
+                        }
+                    });
+                    Ok(UploadHistoryUi {
+                        items,
+                        sync_msg,
+                        sync_ok,
+                    })
+                }
+                .await;
 
-                sender.send(res_result_ui);
+                sender.send(res_result);
+                ctx.request_repaint();
             });
             promise
         });
@@ -207,6 +966,46 @@ impl SmMsApp {
 
 /* #region MyApp panel */
 impl SmMsApp {
+    /// 使用密码加密的 token 需要先解锁
+    fn unlock_panel(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.label(t!("unlock.hint"));
+
+            let res =
+                ui.add(egui::TextEdit::singleline(&mut self.unlock_passphrase).password(true));
+            let submit = res.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+            ui.horizontal(|ui| {
+                let unlocking = self.unlock_promise.is_some();
+                let clicked = ui
+                    .add_enabled(!unlocking, egui::Button::new(t!("unlock.submit")))
+                    .clicked();
+                if (clicked || submit) && !unlocking {
+                    if let Some(encrypted) = self.locked_token.clone() {
+                        self.unlock_err = None;
+                        let passphrase = Some(self.unlock_passphrase.clone());
+                        self.decrypt_token(encrypted, passphrase, ctx);
+                    }
+                }
+                if unlocking {
+                    ui.spinner();
+                }
+
+                // 忘记密码时只能重新登录
+                if ui.button(t!("unlock.relogin")).clicked() {
+                    self.locked_token = None;
+                    self.unlock_promise = None;
+                    self.unlock_passphrase.clear();
+                    self.unlock_err = None;
+                }
+            });
+
+            if let Some(unlock_err) = &self.unlock_err {
+                ui.label(RichText::new(unlock_err).color(Color32::RED));
+            }
+        });
+    }
+
     /// 登录界面
     fn login_panel(&mut self, ctx: &egui::Context) {
         let _my_frame = egui::containers::Frame {
@@ -239,42 +1038,123 @@ impl SmMsApp {
         egui::CentralPanel::default()
             // .frame(my_frame)
             .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut self.login_mode,
+                        LoginMode::Password,
+                        t!("login.mode_password"),
+                    );
+                    ui.radio_value(&mut self.login_mode, LoginMode::Token, "API token");
+                });
+
                 egui::Grid::new("login")
                     .num_columns(2)
                     .striped(false)
                     .show(ui, |ui| {
-                        ui.label("用户名:");
-                        ui.add(egui::TextEdit::singleline(&mut self.username));
-                        ui.end_row();
+                        match self.login_mode {
+                            LoginMode::Password => {
+                                ui.label(t!("login.username"));
+                                ui.add(egui::TextEdit::singleline(&mut self.username));
+                                ui.end_row();
+
+                                ui.label(t!("login.password"));
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.password).password(true),
+                                );
+                                ui.end_row();
+                            }
+                            LoginMode::Token => {
+                                ui.label("API token:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.api_token).password(true),
+                                );
+                                ui.end_row();
+
+                                ui.label(t!("login.account_name"));
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.username)
+                                        .hint_text(t!("login.account_name_hint")),
+                                );
+                                ui.end_row();
+                            }
+                        }
 
-                        ui.label("密码:");
-                        ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+                        ui.label(t!("login.passphrase"));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.login_passphrase)
+                                .password(true)
+                                .hint_text(t!("login.passphrase_hint")),
+                        );
                         ui.end_row();
                     });
 
                 ui.horizontal(|ui| {
-                    let disabel_btn =
-                        self.login_loading || self.username.is_empty() || self.password.is_empty();
+                    let disabel_btn = self.login_loading
+                        || match self.login_mode {
+                            LoginMode::Password => {
+                                self.username.is_empty() || self.password.is_empty()
+                            }
+                            LoginMode::Token => self.api_token.trim().is_empty(),
+                        };
 
                     if ui
-                        .add_enabled(!disabel_btn, egui::Button::new("登录"))
+                        .add_enabled(!disabel_btn, egui::Button::new(t!("login.submit")))
                         .clicked()
                     {
                         self.token_promise = None;
                         self.login_err = None;
                         self.token_promise.get_or_insert_with(|| {
-                            let (u, p) = (self.username.to_owned(), self.password.to_owned());
+                            let mode = self.login_mode;
+                            let (u, p) =
+                                (self.username.trim().to_owned(), self.password.to_owned());
+                            let api_token = self.api_token.trim().to_owned();
+                            let passphrase = (!self.login_passphrase.is_empty())
+                                .then(|| self.login_passphrase.clone());
 
                             let (sender, promise) = Promise::new();
                             self.rt.spawn(async move {
-                                let res_result = api::token(&u, &p).await;
-
-                                if let Ok(res) = &res_result {
-                                    cache::SmMsCacheData::save(cache::SmMsCacheData {
-                                        token: Some(res.to_owned()),
+                                let res_result = async {
+                                    let (name, token, profile) = match mode {
+                                        LoginMode::Password => {
+                                            (u.clone(), api::token(&u, &p).await?, None)
+                                        }
+                                        LoginMode::Token => {
+                                            // 保存前确认 token 有效
+                                            let profile =
+                                                api::profile(&api_token).await.map_err(|err| {
+                                                    anyhow::anyhow!(t!(
+                                                        "login.invalid_token",
+                                                        error = err
+                                                    ))
+                                                })?;
+                                            let name = if u.is_empty() {
+                                                profile.username.clone()
+                                            } else {
+                                                u
+                                            };
+                                            (name, api_token, Some(profile))
+                                        }
+                                    };
+
+                                    // 加密 token 时派生密钥比较耗时
+                                    let saved = token.clone();
+                                    tokio::task::spawn_blocking(move || {
+                                        cache::SmMsCacheData::update(|cache_data| {
+                                            cache_data.add_account(
+                                                &name,
+                                                &saved,
+                                                passphrase.as_deref(),
+                                            )?;
+                                            if let Some(profile) = profile {
+                                                cache_data.set_profile(&name, profile);
+                                            }
+                                            Ok(())
+                                        })
                                     })
-                                    .unwrap();
+                                    .await??;
+                                    Ok(token)
                                 }
+                                .await;
 
                                 sender.send(res_result);
                             });
@@ -287,10 +1167,18 @@ impl SmMsApp {
                     }
                 });
 
-                ui.add(Hyperlink::from_label_and_url(
-                    "Register",
-                    "https://sm.ms/register",
-                ));
+                ui.horizontal(|ui| {
+                    ui.add(Hyperlink::from_label_and_url(
+                        t!("login.register"),
+                        "https://sm.ms/register",
+                    ));
+                    if self.login_mode == LoginMode::Token {
+                        ui.add(Hyperlink::from_label_and_url(
+                            t!("login.get_token"),
+                            "https://sm.ms/home/apitoken",
+                        ));
+                    }
+                });
 
                 if let Some(login_err) = self.login_err.as_mut() {
                     egui::TextEdit::multiline(login_err)
@@ -304,7 +1192,7 @@ impl SmMsApp {
         ui.horizontal(|ui| {
             for (i, label) in K_TABS.iter().enumerate() {
                 if ui
-                    .selectable_label(self.tab_index == i, label.to_string())
+                    .selectable_label(self.tab_index == i, t!(label))
                     .clicked()
                 {
                     self.tab_index = i;
@@ -332,14 +1220,21 @@ impl SmMsApp {
             return;
          };
 
-        // error
-        if let Err(err) = result {
-            widget::error_label(ui, err.to_string());
-            return;
+        let upload_history = match result {
+            Ok(upload_history) => upload_history,
+            // error
+            Err(err) => {
+                widget::error_label(ui, err.to_string());
+                return;
+            }
         };
 
         // show data
-        let Ok(upload_history_v) = result else { todo!() };
+        let upload_history_v: Vec<_> = upload_history
+            .items
+            .iter()
+            .filter(|data| data.matches(&self.history_search))
+            .collect();
 
         egui::ScrollArea::vertical()
             .always_show_scroll(true)
@@ -366,12 +1261,12 @@ impl SmMsApp {
 
                             ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
                                 ui.horizontal(|ui| {
-                                    if ui.button("复制 url").clicked() {
+                                    if ui.button(t!("action.copy_url")).clicked() {
                                         ui.output_mut(|o| {
                                             o.copied_text = data.data.url.clone()
                                         });
                                     }
-                                    if ui.button("打开 url").clicked() {
+                                    if ui.button(t!("action.open_url")).clicked() {
                                         ui.output_mut(|o| {
                                             o.open_url = Some(OpenUrl {
                                                 url: data.data.url.clone(),
@@ -380,7 +1275,7 @@ impl SmMsApp {
                                         });
                                     }
 
-                                    if ui.button("删除").clicked() {
+                                    if ui.button(t!("action.delete")).clicked() {
                                         self.delete_img_hash = Some(data.data.hash.clone());
                                         self.delete_image_model_open = true;
                                     }
@@ -392,23 +1287,733 @@ impl SmMsApp {
             });
     }
 
+    /// 搜索和同步上传历史
+    fn upload_history_toolbar(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.history_search)
+                    .hint_text(t!("history.search_hint")),
+            );
+
+            let syncing = self
+                .upload_history_promise
+                .as_ref()
+                .is_some_and(|p| p.ready().is_none());
+            if ui
+                .add_enabled(!syncing, egui::Button::new(t!("history.sync")))
+                .clicked()
+            {
+                self.upload_history_promise = None;
+                self.get_upload_history_data(ctx);
+            }
+            if ui
+                .add_enabled(!syncing, egui::Button::new(t!("history.full_sync")))
+                .on_hover_text(t!("history.full_sync_hint"))
+                .clicked()
+            {
+                self.upload_history_promise = None;
+                self.upload_history_full_sync = true;
+                self.get_upload_history_data(ctx);
+            }
+
+            if let Some(Ok(history)) = self.upload_history_promise.as_ref().and_then(|p| p.ready())
+            {
+                if history.sync_ok {
+                    ui.label(&history.sync_msg);
+                } else {
+                    widget::warning_label(ui, &history.sync_msg);
+                }
+            }
+        });
+    }
+
+    // 上传界面，上传前显示文件预览和校验结果
+    fn upload_panel(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.label(t!("upload.paths"));
+                let response = ui.text_edit_multiline(&mut self.upload_path);
+                let now = ui.input(|i| i.time);
+                if response.changed() {
+                    self.upload_path_edited = Some(now);
+                }
+                if let Some(edited) = self.upload_path_edited {
+                    let idle = now - edited;
+                    if response.lost_focus() || idle >= K_PREVIEW_DEBOUNCE {
+                        self.refresh_upload_previews(ctx);
+                    } else {
+                        ctx.request_repaint_after(std::time::Duration::from_secs_f64(
+                            K_PREVIEW_DEBOUNCE - idle,
+                        ));
+                    }
+                }
+
+                let previews = self.upload_previews();
+                let disabel_btn = self.upload_path_edited.is_some()
+                    || self.upload_options_edited.is_some()
+                    || !previews.as_ref().is_some_and(|previews| {
+                        !previews.is_empty() && previews.iter().all(|p| p.preview.is_ok())
+                    });
+                if ui
+                    .add_enabled(!disabel_btn, egui::Button::new(t!("upload.submit")))
+                    .clicked()
+                {
+                    self.upload(ctx);
+                }
+
+                let similar = self
+                    .upload_sources
+                    .iter()
+                    .filter_map(|source| source.previews.ready())
+                    .flatten()
+                    .filter_map(|p| p.phash.as_ref())
+                    .filter(|phash| !self.similar_history(phash).is_empty())
+                    .count();
+                if similar > 0 {
+                    widget::warning_label(ui, t!("upload.similar_files", count = similar));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(t!("upload.image_url"));
+                ui.text_edit_singleline(&mut self.upload_url);
+                if ui
+                    .add_enabled(
+                        remote::is_url(self.upload_url.trim()),
+                        egui::Button::new(t!("upload.from_url")),
+                    )
+                    .clicked()
+                {
+                    self.upload_from_url(ctx);
+                }
+            });
+
+            self.scan_folder_panel(ui, ctx);
+
+            ui.label(RichText::new(&self.uplaod_res_msg).color(Color32::RED));
+
+            let now = ui.input(|i| i.time);
+            if self.preprocess_options_panel(ui) {
+                self.upload_options_edited = Some(now);
+            }
+            if let Some(edited) = self.upload_options_edited {
+                let idle = now - edited;
+                // 拖动滑块时不保存，松开后再等待一段时间
+                if ui.input(|i| i.pointer.any_down()) {
+                    self.upload_options_edited = Some(now);
+                } else if idle >= K_PREVIEW_DEBOUNCE {
+                    self.upload_options_edited = None;
+                    self.save_upload_settings();
+                    self.reload_upload_previews(ctx);
+                } else {
+                    ctx.request_repaint_after(std::time::Duration::from_secs_f64(
+                        K_PREVIEW_DEBOUNCE - idle,
+                    ));
+                }
+            }
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    // 编辑文件名时还需要读取上传历史
+                    let mut filenames = std::mem::take(&mut self.upload_filenames);
+                    for source in &self.upload_sources {
+                        let Some(previews) = source.previews.ready() else {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(&source.path);
+                            });
+                            ui.separator();
+                            continue;
+                        };
+                        for p in previews {
+                            ui.horizontal(|ui| {
+                                if let Some(image) = &p.image {
+                                    let w = image.width() as f32;
+                                    let h = image.height() as f32;
+                                    let scale = (K_PREVIEW_MAX_WIDTH / w).min(1.0);
+                                    image.show_size(ui, [w * scale, h * scale].into());
+                                }
+
+                                ui.vertical(|ui| {
+                                    ui.label(p.preview.label());
+                                    ui.label(p.preview.summary());
+                                    ui.horizontal(|ui| {
+                                        ui.label(t!("upload.filename"));
+                                        ui.add(
+                                            egui::TextEdit::singleline(
+                                                filenames.entry(p.preview.label()).or_default(),
+                                            )
+                                            .hint_text(p.preview.filename()),
+                                        );
+                                    });
+                                    for note in &p.preview.notes {
+                                        ui.label(note);
+                                    }
+                                    for problem in &p.preview.problems {
+                                        ui.label(RichText::new(problem).color(Color32::RED));
+                                    }
+
+                                    let Some(phash) = &p.phash else {
+                                        return;
+                                    };
+                                    for (data, distance) in self.similar_history(phash) {
+                                        ui.horizontal(|ui| {
+                                            widget::warning_label(
+                                                ui,
+                                                t!("upload.similar_image", distance = distance),
+                                            );
+                                            ui.add(Hyperlink::from_label_and_url(
+                                                &data.data.filename,
+                                                &data.data.url,
+                                            ));
+                                        });
+                                    }
+                                });
+                            });
+                            ui.separator();
+                        }
+                    }
+                    self.upload_filenames = filenames;
+
+                    self.upload_queue_panel(ui, ctx);
+                });
+        });
+    }
+
+    /// 递归扫描文件夹
+    fn scan_folder_panel(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        self.poll_scan_folder(ctx);
+        ui.collapsing(t!("scan.add_folder"), |ui| {
+            egui::Grid::new("scan folder")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label(t!("scan.folder"));
+                    ui.text_edit_singleline(&mut self.scan_dir);
+                    ui.end_row();
+
+                    ui.label(t!("scan.include"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.scan_include)
+                            .hint_text(scan::K_DEFAULT_INCLUDE),
+                    );
+                    ui.end_row();
+
+                    ui.label(t!("scan.exclude"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.scan_exclude)
+                            .hint_text("**/thumbs/**"),
+                    );
+                    ui.end_row();
+
+                    ui.label(t!("scan.max_depth"));
+                    ui.horizontal(|ui| {
+                        let mut limited = self.scan_options.max_depth.is_some();
+                        if ui.checkbox(&mut limited, t!("scan.limit")).changed() {
+                            self.scan_options.max_depth = limited.then_some(0);
+                        }
+                        if let Some(max_depth) = self.scan_options.max_depth.as_mut() {
+                            ui.add(egui::DragValue::new(max_depth).clamp_range(0..=64));
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.checkbox(
+                        &mut self.scan_options.follow_symlinks,
+                        t!("scan.follow_symlinks"),
+                    );
+                    ui.end_row();
+                });
+
+            let scanning = self.scan_promise.is_some();
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(
+                        !self.scan_dir.is_empty() && !scanning,
+                        egui::Button::new(t!("scan.submit")),
+                    )
+                    .clicked()
+                {
+                    self.scan_folder(ctx);
+                }
+
+                if scanning {
+                    let (visited, found) = self.scan_progress.get();
+                    ui.spinner();
+                    ui.label(t!("scan.progress", visited = visited, found = found));
+                    ctx.request_repaint_after(std::time::Duration::from_millis(100));
+                }
+            });
+        });
+    }
+
+    /// 上传队列，显示每个文件的状态
+    fn upload_queue_panel(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        let summary = self.upload_queue.summary();
+        if summary.total() == 0 {
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            ui.heading(t!("queue.title"));
+            if !summary.is_finished() {
+                ui.spinner();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(t!(
+                "queue.summary",
+                pending = summary.pending,
+                uploading = summary.uploading,
+                done = summary.done,
+                duplicate = summary.duplicate,
+                failed = summary.failed,
+            ));
+
+            if ui
+                .add_enabled(
+                    summary.failed > 0,
+                    egui::Button::new(t!("queue.retry_failed")),
+                )
+                .clicked()
+            {
+                self.upload_queue.retry_failed();
+                self.start_upload_queue(ctx);
+            }
+
+            if ui.button(t!("queue.clear_finished")).clicked() {
+                self.upload_queue.clear_finished();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(t!("queue.manifest"));
+            ui.text_edit_singleline(&mut self.manifest_path);
+            if ui.button(t!("queue.export_manifest")).clicked() {
+                self.uplaod_res_msg = match self
+                    .upload_queue
+                    .write_manifest(std::path::Path::new(&self.manifest_path))
+                {
+                    Ok(_) => t!("queue.exported", path = self.manifest_path),
+                    Err(err) => err.to_string(),
+                };
+            }
+        });
+
+        if summary.is_finished() {
+            ui.label(t!(
+                "queue.finished",
+                done = summary.done,
+                duplicate = summary.duplicate,
+                failed = summary.failed,
+            ));
+        }
+
+        egui::Grid::new("upload queue")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                for item in self.upload_queue.items() {
+                    ui.label(item.label());
+
+                    let status = RichText::new(item.status.label());
+                    ui.label(match item.status {
+                        queue::UploadStatus::Failed => status.color(Color32::RED),
+                        queue::UploadStatus::Done => status.color(Color32::GREEN),
+                        _ => status,
+                    });
+
+                    ui.horizontal(|ui| {
+                        if let Some(url) = &item.url {
+                            ui.add(Hyperlink::new(url));
+                            if ui.button(t!("action.copy_url")).clicked() {
+                                ui.output_mut(|o| o.copied_text = url.clone());
+                            }
+                        }
+
+                        if item.status == queue::UploadStatus::Failed
+                            && ui.button(t!("queue.retry")).clicked()
+                        {
+                            self.upload_queue.retry(item.id);
+                            self.start_upload_queue(ctx);
+                        }
+                    });
+
+                    let mut messages = item.messages.clone();
+                    if item.status == queue::UploadStatus::Pending
+                        && item.account.is_some()
+                        && item.account != self.account
+                    {
+                        messages.push(t!(
+                            "queue.other_account",
+                            account = item.account.as_deref().unwrap_or_default(),
+                        ));
+                    }
+                    ui.label(messages.join("\n"));
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// 上传前的预处理设置，返回设置是否被修改
+    fn preprocess_options_panel(&mut self, ui: &mut Ui) -> bool {
+        let options = &mut self.preprocess_options;
+        let mut changed = false;
+
+        ui.collapsing(t!("options.title"), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(t!("options.concurrency"));
+                changed |= ui
+                    .add(egui::DragValue::new(&mut self.upload_concurrency).clamp_range(1..=16))
+                    .changed();
+            });
+
+            changed |= ui
+                .checkbox(&mut options.strip_metadata, t!("options.strip_metadata"))
+                .changed();
+
+            changed |= ui
+                .checkbox(&mut options.compress, t!("options.compress"))
+                .changed();
+
+            ui.horizontal(|ui| {
+                ui.label(t!("options.format"));
+                egui::ComboBox::from_id_source("output format")
+                    .selected_text(options.output_format.label())
+                    .show_ui(ui, |ui| {
+                        for format in convert::OutputFormat::ALL {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut options.output_format,
+                                    format,
+                                    format.label(),
+                                )
+                                .changed();
+                        }
+                    });
+
+                ui.label(t!("options.svg_scale"));
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut options.svg_scale)
+                            .speed(0.1)
+                            .clamp_range(0.1..=10.0),
+                    )
+                    .changed();
+            });
+
+            ui.add_enabled_ui(options.compress, |ui| {
+                egui::Grid::new("preprocess options")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label(t!("options.max_width"));
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut options.max_width).clamp_range(1..=16384),
+                            )
+                            .changed();
+                        ui.end_row();
+
+                        ui.label(t!("options.max_height"));
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut options.max_height)
+                                    .clamp_range(1..=16384),
+                            )
+                            .changed();
+                        ui.end_row();
+
+                        ui.label(t!("options.jpeg_quality"));
+                        changed |= ui
+                            .add(egui::Slider::new(&mut options.jpeg_quality, 1..=100))
+                            .changed();
+                        ui.end_row();
+
+                        ui.label(t!("options.webp_quality"));
+                        changed |= ui
+                            .add(egui::Slider::new(&mut options.webp_quality, 1..=100))
+                            .changed();
+                        ui.end_row();
+                    });
+            });
+
+            changed |= Self::watermark_options_panel(ui, &mut options.watermark);
+            changed |= Self::rename_options_panel(ui, &mut options.rename);
+        });
+
+        changed
+    }
+
+    /// 文件名设置，返回设置是否被修改
+    fn rename_options_panel(ui: &mut Ui, options: &mut rename::RenameOptions) -> bool {
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            ui.label(t!("upload.filename"));
+            egui::ComboBox::from_id_source("rename policy")
+                .selected_text(options.policy.label())
+                .show_ui(ui, |ui| {
+                    for policy in rename::RenamePolicy::ALL {
+                        changed |= ui
+                            .selectable_value(&mut options.policy, policy, policy.label())
+                            .changed();
+                    }
+                });
+
+            if options.policy == rename::RenamePolicy::Template {
+                changed |= ui
+                    .add(
+                        egui::TextEdit::singleline(&mut options.template)
+                            .hint_text(rename::K_DEFAULT_TEMPLATE),
+                    )
+                    .on_hover_text(t!("options.rename_hint"))
+                    .changed();
+            }
+        });
+
+        changed
+    }
+
+    /// 水印设置，返回设置是否被修改
+    fn watermark_options_panel(ui: &mut Ui, options: &mut watermark::WatermarkOptions) -> bool {
+        let mut changed = false;
+
+        changed |= ui
+            .checkbox(&mut options.enabled, t!("watermark.enabled"))
+            .changed();
+
+        ui.add_enabled_ui(options.enabled, |ui| {
+            egui::Grid::new("watermark options")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label(t!("watermark.kind"));
+                    ui.horizontal(|ui| {
+                        changed |= ui
+                            .radio_value(
+                                &mut options.kind,
+                                watermark::WatermarkKind::Text,
+                                t!("watermark.text"),
+                            )
+                            .changed();
+                        changed |= ui
+                            .radio_value(
+                                &mut options.kind,
+                                watermark::WatermarkKind::Logo,
+                                t!("watermark.logo"),
+                            )
+                            .changed();
+                    });
+                    ui.end_row();
+
+                    match options.kind {
+                        watermark::WatermarkKind::Text => {
+                            ui.label(t!("watermark.text_label"));
+                            changed |= ui.text_edit_singleline(&mut options.text).changed();
+                            ui.end_row();
+
+                            ui.label(t!("watermark.color"));
+                            changed |= ui.color_edit_button_srgb(&mut options.color).changed();
+                            ui.end_row();
+
+                            ui.label(t!("settings.font_path"));
+                            changed |= ui
+                                .add(
+                                    egui::TextEdit::singleline(&mut options.font_path)
+                                        .hint_text(t!("watermark.font_hint")),
+                                )
+                                .changed();
+                            ui.end_row();
+                        }
+                        watermark::WatermarkKind::Logo => {
+                            ui.label(t!("watermark.logo_path"));
+                            changed |= ui
+                                .add(
+                                    egui::TextEdit::singleline(&mut options.logo_path)
+                                        .hint_text(t!("watermark.logo_hint")),
+                                )
+                                .changed();
+                            ui.end_row();
+                        }
+                    }
+
+                    ui.label(t!("watermark.position"));
+                    egui::ComboBox::from_id_source("watermark position")
+                        .selected_text(options.position.label())
+                        .show_ui(ui, |ui| {
+                            for position in watermark::WatermarkPosition::ALL {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut options.position,
+                                        position,
+                                        position.label(),
+                                    )
+                                    .changed();
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label(t!("watermark.opacity"));
+                    changed |= ui
+                        .add(egui::Slider::new(&mut options.opacity, 0.0..=1.0))
+                        .changed();
+                    ui.end_row();
+
+                    ui.label(t!("watermark.scale"));
+                    changed |= ui
+                        .add(egui::Slider::new(&mut options.scale, 0.01..=1.0))
+                        .changed();
+                    ui.end_row();
+                });
+        });
+
+        changed
+    }
+
+    // 显示上传历史中相似的图片
+    fn similar_images_panel(&mut self, ui: &mut Ui, _ctx: &egui::Context) {
+        let Some(upload_history_p) = &self.upload_history_promise else {
+            return;
+        };
+
+        let Some(result) = upload_history_p.ready() else {
+            ui.spinner();
+            return;
+        };
+
+        let upload_history_v = match result {
+            Ok(upload_history_v) => upload_history_v,
+            Err(err) => {
+                widget::error_label(ui, err.to_string());
+                return;
+            }
+        };
+
+        let upload_history_v = &upload_history_v.items;
+        let pending = upload_history_v
+            .iter()
+            .filter(|data| data.phash_promise.ready().is_none())
+            .count();
+        if pending > 0 {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(t!("similar.computing", count = pending));
+            });
+        }
+
+        let mut pairs = vec![];
+        for (i, a) in upload_history_v.iter().enumerate() {
+            let Some(a_hash) = a.phash() else {
+                continue;
+            };
+            for b in &upload_history_v[i + 1..] {
+                let Some(b_hash) = b.phash() else {
+                    continue;
+                };
+                let distance = a_hash.distance(b_hash);
+                if distance <= phash::K_SIMILAR_THRESHOLD {
+                    pairs.push((a, b, distance));
+                }
+            }
+        }
+        pairs.sort_by_key(|(_, _, distance)| *distance);
+
+        if pairs.is_empty() && pending == 0 {
+            ui.label(t!("similar.none"));
+        }
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                for (a, b, distance) in pairs {
+                    ui.horizontal(|ui| {
+                        for data in [a, b] {
+                            ui.vertical(|ui| {
+                                data.show_thumbnail(ui, K_PREVIEW_MAX_WIDTH);
+                                ui.add(Hyperlink::from_label_and_url(
+                                    &data.data.filename,
+                                    &data.data.url,
+                                ));
+                                ui.label(&data.data.created_at);
+                                if ui.button(t!("action.delete")).clicked() {
+                                    self.delete_img_hash = Some(data.data.hash.clone());
+                                    self.delete_image_model_open = true;
+                                }
+                            });
+                        }
+                        widget::warning_label(ui, t!("similar.distance", distance = distance));
+                    });
+                    ui.separator();
+                }
+            });
+    }
+
     // 显示账号信息
-    fn profile_panel(&mut self, ui: &mut Ui, _ctx: &egui::Context) {
+    fn profile_panel(&mut self, ui: &mut Ui, ctx: &egui::Context) {
+        // 上传和删除后会清除，重新获取最新的空间使用情况
+        self.get_profile_data(ctx);
+
+        let loading = self
+            .profile_promise
+            .as_ref()
+            .is_some_and(|p| p.ready().is_none());
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!loading, egui::Button::new(t!("profile.refresh")))
+                .clicked()
+            {
+                self.profile_promise = None;
+            }
+        });
+
         if let Some(profile_p) = &self.profile_promise {
             match profile_p.ready() {
                 Some(result) => match result {
                     Ok(profile_data) => {
                         ui.vertical(|ui| {
-                            widget::info_row(ui, "username: ", &profile_data.username);
-                            widget::info_row(ui, "email: ", &profile_data.email);
-                            widget::info_row(ui, "role: ", &profile_data.role);
-                            widget::info_row(ui, "disk_usage: ", &profile_data.disk_usage);
-                            widget::info_row(ui, "disk_limit: ", &profile_data.disk_limit);
+                            widget::info_row(ui, t!("profile.username"), &profile_data.username);
+                            widget::info_row(ui, t!("profile.email"), &profile_data.email);
+                            if profile_data.email_verified == 0 {
+                                widget::warning_label(ui, t!("profile.email_unverified"));
+                            }
+                            widget::info_row(ui, t!("profile.role"), &profile_data.role);
+
+                            let now = util::now_local();
+                            if let Some(expiry) = profile::GroupExpiry::new(profile_data, now) {
+                                let text =
+                                    format!("{} ({})", profile_data.group_expire, expiry.text());
+                                if expiry.is_soon() {
+                                    widget::warning_row(ui, t!("profile.group_expire"), text);
+                                } else {
+                                    widget::info_row(ui, t!("profile.group_expire"), text);
+                                }
+                            }
+
+                            ui.add_space(8.0);
+                            let quota = profile::Quota::new(profile_data);
+                            widget::info_row(
+                                ui,
+                                t!("profile.disk_usage"),
+                                format!(
+                                    "{} / {}",
+                                    profile_data.disk_usage, profile_data.disk_limit
+                                ),
+                            );
+                            let mut bar = egui::ProgressBar::new(quota.ratio)
+                                .desired_width(K_IMAGE_MAX_WIDTH)
+                                .show_percentage();
+                            if quota.is_low() {
+                                bar = bar.fill(Color32::RED);
+                            }
+                            ui.add(bar);
+                            ui.label(quota.remaining_text());
                         });
                     }
                     Err(err) => {
                         ui.label(
-                            RichText::new(&err.to_string())
+                            RichText::new(err.to_string())
                                 .size(20.0)
                                 .color(Color32::RED),
                         );
@@ -422,36 +2027,84 @@ impl SmMsApp {
 
         ui.separator();
 
-        if widget::error_button(ui, "退出登录").clicked() {
+        // 只结束当前会话，保存的账号可以在账号菜单中切换回来或者移除
+        if widget::error_button(ui, t!("account.logout")).clicked() {
             self.token.clear();
             self.token_promise = None;
-            cache::SmMsCacheData::save(cache::SmMsCacheData { token: None }).unwrap();
+            self.upload_queue.stop(self.account.as_deref());
+            // 下次启动不再自动登录
+            let res = cache::SmMsCacheData::update(|cache_data| {
+                cache_data.current_account = None;
+                Ok(())
+            });
+            if let Err(err) = res {
+                dbg!(err);
+            }
+            self.set_account(None);
+            self.reload_accounts();
+        };
+    }
+
+    /// 确认后删除保存的账号，包括 token、水印设置和账号信息
+    fn remove_account_window(&mut self, ctx: &egui::Context) {
+        let Some(name) = self.remove_account_name.clone() else {
+            return;
         };
+
+        egui::Window::new(t!("account.remove"))
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(t!("account.remove_confirm", name = name));
+
+                ui.horizontal(|ui| {
+                    if widget::error_button(ui, t!("account.remove_ok")).clicked() {
+                        self.upload_queue.stop(Some(&name));
+                        let res = cache::SmMsCacheData::update(|cache_data| {
+                            cache_data.remove_account(&name);
+                            Ok(())
+                        });
+                        if let Err(err) = res {
+                            dbg!(err);
+                        }
+                        if self.account.as_deref() == Some(name.as_str()) {
+                            self.token.clear();
+                            self.token_promise = None;
+                            self.set_account(None);
+                        }
+                        self.reload_accounts();
+                        self.remove_account_name = None;
+                    }
+
+                    if ui.button(t!("account.remove_cancel")).clicked() {
+                        self.remove_account_name = None;
+                    }
+                });
+            });
     }
 
     /// 登录后界面
     fn dashboard_panel(&mut self, ctx: &egui::Context) {
+        // 有新的文件上传完成时刷新上传历史
+        let done = self.upload_queue.summary().done;
+        if done != self.upload_queue_done {
+            self.upload_queue_done = done;
+            self.upload_history_promise = None;
+            self.profile_promise = None;
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical(|ui| {
                 self.tabs_panel(ui, ctx);
                 ui.separator();
 
                 match self.tab_index {
-                    0 => self.images_grid_panel(ui, ctx),
-                    1 => {
-                        ui.vertical(|ui| {
-                            ui.horizontal(|ui| {
-                                ui.label("文件路径：");
-                                ui.text_edit_singleline(&mut self.upload_path);
-                                if ui.button("上传").clicked() {
-                                    self.upload();
-                                }
-                            });
-
-                            ui.label(RichText::new(&self.uplaod_res_msg).color(Color32::RED));
-                        });
+                    0 => {
+                        self.upload_history_toolbar(ui, ctx);
+                        self.images_grid_panel(ui, ctx);
                     }
+                    1 => self.upload_panel(ui, ctx),
                     2 => self.profile_panel(ui, ctx),
+                    3 => self.similar_images_panel(ui, ctx),
                     _ => {
                         ui.label("??");
                     }
@@ -460,15 +2113,141 @@ impl SmMsApp {
         });
     }
 
+    /// 账号菜单，切换保存的账号
+    fn account_menu(&mut self, ui: &mut Ui) {
+        let title = self
+            .account
+            .as_deref()
+            .unwrap_or(t!("account.menu"))
+            .to_owned();
+        let menu = ui.menu_button(title, |ui| {
+            let mut selected = None;
+            for account in &self.accounts {
+                // 显示最后一次获取到的空间使用情况
+                let label = match &account.profile {
+                    Some(profile) => format!(
+                        "{} ({} / {})",
+                        account.name, profile.disk_usage, profile.disk_limit
+                    ),
+                    None => account.name.clone(),
+                };
+                let current = self.account.as_deref() == Some(account.name.as_str());
+                if ui.selectable_label(current, label).clicked() {
+                    if !current {
+                        selected = Some(account.clone());
+                    }
+                    ui.close_menu();
+                }
+            }
+
+            // 保存选择的账号，下次启动和命令行默认使用
+            if let Some(account) = selected {
+                self.use_account(&account, ui.ctx());
+                let res = cache::SmMsCacheData::update(|cache_data| {
+                    cache_data.current_account = Some(account.name.clone());
+                    Ok(())
+                });
+                if let Err(err) = res {
+                    dbg!(err);
+                }
+            }
+
+            if !self.accounts.is_empty() {
+                ui.separator();
+            }
+
+            if ui.button(t!("account.add")).clicked() {
+                self.token.clear();
+                self.token_promise = None;
+                self.username.clear();
+                self.password.clear();
+                self.login_err = None;
+                self.set_account(None);
+                ui.close_menu();
+            }
+
+            if let Some(account) = &self.account {
+                if ui.button(t!("account.remove")).clicked() {
+                    self.remove_account_name = Some(account.clone());
+                    ui.close_menu();
+                }
+            }
+        });
+
+        // 打开菜单时读取一次，显示后台更新的空间使用情况
+        if menu.response.clicked() {
+            self.reload_accounts();
+        }
+    }
+
+    /// 设置菜单
+    fn settings_menu(&mut self, ui: &mut Ui) {
+        ui.menu_button(t!("settings.menu"), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(t!("settings.language"));
+                let selected = self.language;
+                egui::ComboBox::from_id_source("language")
+                    .selected_text(
+                        selected.map_or(t!("settings.language_system"), i18n::Lang::label),
+                    )
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut self.language,
+                            None,
+                            t!("settings.language_system"),
+                        );
+                        for lang in i18n::Lang::ALL {
+                            ui.selectable_value(&mut self.language, Some(lang), lang.label());
+                        }
+                    });
+                if self.language != selected {
+                    i18n::set_lang(self.language.unwrap_or_else(i18n::Lang::detect));
+
+                    let res = cache::SmMsCacheData::update(|cache_data| {
+                        cache_data.language = self.language;
+                        Ok(())
+                    });
+                    if let Err(err) = res {
+                        dbg!(err);
+                    }
+                }
+            });
+            ui.separator();
+
+            ui.label(t!("settings.current_font", font = self.font_name));
+            ui.horizontal(|ui| {
+                ui.label(t!("settings.font_path"));
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.font_path)
+                        .hint_text(t!("settings.font_hint")),
+                );
+            });
+
+            if ui.button(t!("settings.apply")).clicked() {
+                self.font_name = util::setup_custom_fonts(ui.ctx(), &self.font_path);
+
+                let res = cache::SmMsCacheData::update(|cache_data| {
+                    cache_data.font_path = self.font_path.trim().to_owned();
+                    Ok(())
+                });
+                if let Err(err) = res {
+                    dbg!(err);
+                }
+            }
+        });
+    }
+
     fn menu_panel(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         // 顶部菜单栏
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
-                ui.menu_button("File", |ui| {
-                    if ui.button("Quit").clicked() {
+                ui.menu_button(t!("menu.file"), |ui| {
+                    if ui.button(t!("menu.quit")).clicked() {
                         frame.close();
                     }
                 });
+                self.account_menu(ui);
+                self.settings_menu(ui);
             });
         });
     }
@@ -476,32 +2255,64 @@ impl SmMsApp {
 /* #endregion */
 
 impl eframe::App for SmMsApp {
+    /// 修改上传设置后马上关闭窗口时还没有保存
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if self.upload_options_edited.take().is_some() {
+            self.save_upload_settings();
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.check_session();
         self.menu_panel(ctx, frame);
 
+        self.remove_account_window(ctx);
+
+        self.config_notes.extend(config::take_notes());
+        if !self.config_notes.is_empty() {
+            egui::Window::new(t!("config.notes_title"))
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    for note in &self.config_notes {
+                        ui.label(note);
+                    }
+                    if ui.button(t!("config.notes_close")).clicked() {
+                        self.config_notes.clear();
+                    }
+                });
+        }
+
         if self.delete_image_model_open {
-            egui::Window::new("Modal Window")
+            egui::Window::new(t!("delete.title"))
                 .default_open(true)
                 .default_width(120f32)
                 .default_height(80f32)
                 .show(ctx, |ui| {
                     ui.vertical(|ui| {
-                        ui.label("确定删除吗?");
+                        ui.label(t!("delete.confirm"));
 
                         ui.horizontal(|ui| {
-                            if widget::error_button(ui, "确定").clicked() {
+                            if widget::error_button(ui, t!("delete.ok")).clicked() {
                                 let hash = self.delete_img_hash.clone().unwrap();
                                 let res = self.rt.block_on(async {
                                     api::delete_image(&self.token, &hash).await
                                 });
-                                if res.is_ok() {
-                                    self.upload_history_promise = None;
-                                    self.get_upload_history_data(&ctx);
+                                match res {
+                                    Ok(_) => {
+                                        self.forget_uploaded(&hash);
+                                        self.upload_history_promise = None;
+                                        self.profile_promise = None;
+                                        self.get_upload_history_data(ctx);
+                                    }
+                                    Err(err) if api::is_unauthorized(&err) => {
+                                        self.expire_session(err.to_string())
+                                    }
+                                    Err(_) => {}
                                 }
                                 self.delete_image_model_open = false;
                             }
 
-                            if ui.button("取消").clicked() {
+                            if ui.button(t!("delete.cancel")).clicked() {
                                 self.delete_image_model_open = false;
                             }
                         });
@@ -509,7 +2320,12 @@ impl eframe::App for SmMsApp {
                 });
         }
 
-        if let Some(token_promise) = &self.token_promise {
+        self.poll_unlock();
+        if self.locked_token.is_some() {
+            self.unlock_panel(ctx);
+        } else if self.unlock_promise.is_some() {
+            egui::CentralPanel::default().show(ctx, |ui| ui.spinner());
+        } else if let Some(token_promise) = &self.token_promise {
             match token_promise.ready() {
                 Some(result) => match result {
                     Ok(token) => {
@@ -517,6 +2333,19 @@ impl eframe::App for SmMsApp {
                             dbg!("token ok");
                             self.login_loading = false;
                             self.token = token.clone();
+                            self.password.clear();
+                            self.api_token.clear();
+                            // 使用 API token 登录时账号名可能来自 sm.ms 用户名，以保存的为准
+                            let account = cache::SmMsCacheData::from()
+                                .ok()
+                                .and_then(|cache_data| cache_data.current_account);
+                            self.set_account(account);
+                            self.reload_accounts();
+                        }
+
+                        if !self.upload_queue_resumed {
+                            self.upload_queue_resumed = true;
+                            self.start_upload_queue(ctx);
                         }
 
                         self.get_upload_history_data(ctx);
@@ -540,12 +2369,5 @@ impl eframe::App for SmMsApp {
         } else {
             self.login_panel(ctx);
         }
-
-        // egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
-        //     ui.horizontal(|ui| {
-        //         ui.label("Action Status: ");
-        //         ui.label(&self.action_status);
-        //     });
-        // });
     }
 }
@@ -0,0 +1,287 @@
+use image::{DynamicImage, ImageFormat};
+
+use crate::i18n::t;
+
+/// 读取 EXIF 中的图片方向，1 表示不需要旋转
+pub fn orientation(bytes: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(bytes))
+        .ok()?;
+
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// 按照 EXIF 方向旋转图片，使其以正确的方向显示
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// 在不重新编码的情况下移除 EXIF/XMP 等元数据
+///
+/// 只处理 JPEG、PNG 和 WebP，其它格式原样返回
+pub fn strip(bytes: &[u8], format: ImageFormat) -> anyhow::Result<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => strip_jpeg(bytes),
+        ImageFormat::Png => strip_png(bytes),
+        ImageFormat::WebP => strip_webp(bytes),
+        _ => Ok(bytes.to_vec()),
+    }
+}
+
+/// 移除 APP1(EXIF/XMP)、APP13(IPTC) 和注释段
+fn strip_jpeg(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const APP1: u8 = 0xE1;
+    const APP13: u8 = 0xED;
+    const COM: u8 = 0xFE;
+    const SOS: u8 = 0xDA;
+
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        anyhow::bail!(t!("metadata.invalid_jpeg"));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+
+    let mut i = 2;
+    while i + 4 <= bytes.len() {
+        if bytes[i] != 0xFF {
+            anyhow::bail!(t!("metadata.invalid_jpeg"));
+        }
+
+        let marker = bytes[i + 1];
+        // 填充字节
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+
+        let len = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+        let end = i + 2 + len;
+        if end > bytes.len() {
+            anyhow::bail!(t!("metadata.invalid_jpeg"));
+        }
+
+        // 图像数据开始，之后不再有元数据段
+        if marker == SOS {
+            out.extend_from_slice(&bytes[i..]);
+            return Ok(out);
+        }
+
+        if !matches!(marker, APP1 | APP13 | COM) {
+            out.extend_from_slice(&bytes[i..end]);
+        }
+        i = end;
+    }
+
+    anyhow::bail!(t!("metadata.invalid_jpeg"))
+}
+
+/// 移除 eXIf 和文本块
+fn strip_png(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    const REMOVED: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+    if !bytes.starts_with(&SIGNATURE) {
+        anyhow::bail!(t!("metadata.invalid_png"));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&SIGNATURE);
+
+    let mut i = SIGNATURE.len();
+    while i + 8 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[i..i + 4].try_into()?) as usize;
+        let kind = &bytes[i + 4..i + 8];
+        // length + type + data + crc
+        let end = i + 12 + len;
+        if end > bytes.len() {
+            anyhow::bail!(t!("metadata.invalid_png"));
+        }
+
+        if !REMOVED.iter().any(|r| r.as_slice() == kind) {
+            out.extend_from_slice(&bytes[i..end]);
+        }
+        i = end;
+    }
+
+    Ok(out)
+}
+
+/// 移除 EXIF 和 XMP 块，并清除 VP8X 中对应的标记位
+fn strip_webp(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const FLAG_EXIF: u8 = 0x08;
+    const FLAG_XMP: u8 = 0x04;
+
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        anyhow::bail!(t!("metadata.invalid_webp"));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..12]);
+
+    let mut i = 12;
+    while i + 8 <= bytes.len() {
+        let kind = &bytes[i..i + 4];
+        let len = u32::from_le_bytes(bytes[i + 4..i + 8].try_into()?) as usize;
+        // 块数据按偶数字节对齐
+        let end = (i + 8 + len + len % 2).min(bytes.len());
+
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = out.len();
+                out.extend_from_slice(&bytes[i..end]);
+                if let Some(flags) = out.get_mut(start + 8) {
+                    *flags &= !(FLAG_EXIF | FLAG_XMP);
+                }
+            }
+            _ => out.extend_from_slice(&bytes[i..end]),
+        }
+        i = end;
+    }
+
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    /// 只有一个 Orientation 字段的 EXIF，大端序
+    fn exif_app1(orientation: u16) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend_from_slice(&orientation.to_be_bytes());
+        exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(&exif);
+        segment
+    }
+
+    fn encoded(format: ImageFormat) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([200, 100, 50])));
+        let mut bytes = vec![];
+        img.write_to(&mut std::io::Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn strip_jpeg_removes_exif_and_comment() {
+        let original = encoded(ImageFormat::Jpeg);
+        let mut bytes = original[..2].to_vec();
+        bytes.extend(exif_app1(6));
+        bytes.extend_from_slice(&[0xFF, 0xFE, 0x00, 0x05, b'h', b'i', b'!']);
+        bytes.extend_from_slice(&original[2..]);
+        assert_eq!(orientation(&bytes), Some(6));
+
+        let stripped = strip(&bytes, ImageFormat::Jpeg).unwrap();
+        assert_eq!(stripped, original);
+        assert_eq!(orientation(&stripped), None);
+    }
+
+    #[test]
+    fn strip_jpeg_rejects_other_data() {
+        assert!(strip(b"not a jpeg", ImageFormat::Jpeg).is_err());
+    }
+
+    #[test]
+    fn strip_png_removes_text_chunks() {
+        let original = encoded(ImageFormat::Png);
+        // 签名和 IHDR 块之后插入 tEXt 块，crc 不会被校验
+        let ihdr_end = 8 + 12 + 13;
+        let mut bytes = original[..ihdr_end].to_vec();
+        bytes.extend_from_slice(&5u32.to_be_bytes());
+        bytes.extend_from_slice(b"tEXtGPS\0x");
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&original[ihdr_end..]);
+
+        let stripped = strip(&bytes, ImageFormat::Png).unwrap();
+        assert_eq!(stripped, original);
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn strip_webp_removes_exif_and_clears_flags() {
+        fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+            let mut chunk = kind.to_vec();
+            chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(data);
+            if !data.len().is_multiple_of(2) {
+                chunk.push(0);
+            }
+            chunk
+        }
+
+        let vp8x = chunk(b"VP8X", &[0x08 | 0x04, 0, 0, 0, 3, 0, 0, 3, 0, 0]);
+        let image = chunk(b"VP8L", &[1, 2, 3, 4]);
+        let mut body = b"WEBP".to_vec();
+        body.extend(vp8x.clone());
+        body.extend(chunk(b"EXIF", b"odd"));
+        body.extend(chunk(b"XMP ", b"<x/>"));
+        body.extend(image.clone());
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+
+        let stripped = strip(&bytes, ImageFormat::WebP).unwrap();
+        let mut cleared = vp8x;
+        cleared[8] = 0;
+        let mut expected = b"RIFF".to_vec();
+        expected.extend_from_slice(&(4 + cleared.len() as u32 + image.len() as u32).to_le_bytes());
+        expected.extend_from_slice(b"WEBP");
+        expected.extend(cleared);
+        expected.extend(image);
+        assert_eq!(stripped, expected);
+    }
+
+    #[test]
+    fn apply_orientation_matches_exif() {
+        // 2x1: 左边红色，右边绿色
+        let red = Rgb([255, 0, 0]);
+        let green = Rgb([0, 255, 0]);
+        let img =
+            DynamicImage::ImageRgb8(RgbImage::from_fn(
+                2,
+                1,
+                |x, _| {
+                    if x == 0 {
+                        red
+                    } else {
+                        green
+                    }
+                },
+            ));
+        let pixels = |orientation| {
+            let img = apply_orientation(img.clone(), orientation).to_rgb8();
+            (img.dimensions(), img.pixels().copied().collect::<Vec<_>>())
+        };
+
+        assert_eq!(pixels(1), ((2, 1), vec![red, green]));
+        assert_eq!(pixels(2), ((2, 1), vec![green, red]));
+        assert_eq!(pixels(3), ((2, 1), vec![green, red]));
+        assert_eq!(pixels(4), ((2, 1), vec![red, green]));
+        assert_eq!(pixels(5), ((1, 2), vec![red, green]));
+        assert_eq!(pixels(6), ((1, 2), vec![red, green]));
+        assert_eq!(pixels(7), ((1, 2), vec![green, red]));
+        assert_eq!(pixels(8), ((1, 2), vec![green, red]));
+        assert_eq!(pixels(9), ((2, 1), vec![red, green]));
+    }
+}
@@ -0,0 +1,100 @@
+use image::ImageFormat;
+
+use crate::{i18n::t, preflight, util};
+
+/// 根据文件头识别 sm.ms 接受的图片格式，不看扩展名
+pub fn sniff(bytes: &[u8]) -> Option<ImageFormat> {
+    image::guess_format(bytes)
+        .ok()
+        .filter(|format| preflight::K_ACCEPTED_FORMATS.contains(format))
+}
+
+pub fn mime_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Png => "image/png",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::Bmp => "image/bmp",
+        ImageFormat::WebP => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 确认文件头是 `format`，扩展名与内容不符或者没有扩展名时返回修正后的文件名，例如 a.jpg -> a.png
+///
+/// 上传前的预处理和预检都使用这个检查
+pub fn check(bytes: &[u8], format: ImageFormat, filename: &str) -> anyhow::Result<Option<String>> {
+    if sniff(bytes) != Some(format) {
+        anyhow::bail!(t!(
+            "preprocess.mime_mismatch",
+            format = preflight::format_name(format)
+        ));
+    }
+
+    let ext = std::path::Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
+
+    match ext {
+        Some(ext) if format.extensions_str().contains(&ext.as_str()) => Ok(None),
+        _ => Ok(Some(util::replace_extension(filename, format))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+
+    fn encoded(format: ImageFormat) -> Vec<u8> {
+        let mut bytes = vec![];
+        DynamicImage::ImageRgb8(RgbImage::new(2, 2))
+            .write_to(&mut std::io::Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn sniff_accepted_formats() {
+        for format in preflight::K_ACCEPTED_FORMATS {
+            if format == ImageFormat::WebP {
+                continue;
+            }
+            assert_eq!(sniff(&encoded(format)), Some(format));
+        }
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(ImageFormat::WebP));
+        assert_eq!(sniff(&encoded(ImageFormat::Tiff)), None);
+        assert_eq!(sniff(b"not an image"), None);
+    }
+
+    #[test]
+    fn matching_extension_is_kept() {
+        let png = encoded(ImageFormat::Png);
+        assert_eq!(check(&png, ImageFormat::Png, "a.png").unwrap(), None);
+        assert_eq!(check(&png, ImageFormat::Png, "a.PNG").unwrap(), None);
+
+        let jpeg = encoded(ImageFormat::Jpeg);
+        assert_eq!(check(&jpeg, ImageFormat::Jpeg, "a.jpeg").unwrap(), None);
+    }
+
+    #[test]
+    fn wrong_or_missing_extension_is_fixed() {
+        let png = encoded(ImageFormat::Png);
+        assert_eq!(
+            check(&png, ImageFormat::Png, "a.jpg").unwrap().as_deref(),
+            Some("a.png")
+        );
+        assert_eq!(
+            check(&png, ImageFormat::Png, "a").unwrap().as_deref(),
+            Some("a.png")
+        );
+    }
+
+    #[test]
+    fn content_mismatch_is_an_error() {
+        let png = encoded(ImageFormat::Png);
+        assert!(check(&png, ImageFormat::Jpeg, "a.jpg").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use image::{DynamicImage, GenericImageView};
+
+/// 汉明距离不超过该值时认为两张图片相似
+pub const K_SIMILAR_THRESHOLD: u32 = 10;
+
+/// 基于 dHash 的感知哈希
+///
+/// 同时计算整张图片和中心区域的哈希，裁剪过边缘的图片也能匹配上
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerceptualHash {
+    full: u64,
+    center: u64,
+}
+
+impl PerceptualHash {
+    pub fn from_image(img: &DynamicImage) -> Self {
+        let (w, h) = img.dimensions();
+        let (cw, ch) = ((w * 8 / 10).max(1), (h * 8 / 10).max(1));
+        let center = img.crop_imm((w - cw) / 2, (h - ch) / 2, cw, ch);
+
+        Self {
+            full: dhash(img),
+            center: dhash(&center),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        image::load_from_memory(bytes)
+            .ok()
+            .map(|img| Self::from_image(&img))
+    }
+
+    /// 两张图片之间的距离，越小越相似
+    pub fn distance(&self, other: &Self) -> u32 {
+        [
+            hamming(self.full, other.full),
+            hamming(self.center, other.center),
+            hamming(self.full, other.center),
+            hamming(self.center, other.full),
+        ]
+        .into_iter()
+        .min()
+        .unwrap_or(u64::BITS)
+    }
+}
+
+/// 缩小为 9x8 的灰度图，比较相邻像素的亮度
+fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.thumbnail_exact(9, 8).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
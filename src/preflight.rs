@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+
+use crate::{
+    archive, convert,
+    i18n::t,
+    mime,
+    preprocess::{self, PreprocessOptions},
+    util,
+};
+
+/// sm.ms 单个文件的大小上限: 5MB
+pub const K_MAX_UPLOAD_SIZE: u64 = 5 * 1024 * 1024;
+
+/// sm.ms 接受的图片格式
+pub const K_ACCEPTED_FORMATS: [ImageFormat; 5] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::WebP,
+];
+
+/// 上传前对本地文件的预览信息
+#[derive(Debug, Clone)]
+pub struct UploadPreview {
+    pub path: PathBuf,
+    /// 压缩包内的文件路径，此时 `path` 是压缩包
+    pub entry: Option<String>,
+    /// 文件字节数
+    pub size: u64,
+    /// 根据文件头识别出的格式
+    pub format: Option<ImageFormat>,
+    /// image 无法识别 SVG，单独标记
+    pub svg: bool,
+    pub dimensions: Option<(u32, u32)>,
+    /// 校验未通过的原因，为空表示可以上传
+    pub problems: Vec<String>,
+    /// 上传前会自动处理的问题，例如超出大小会被压缩
+    pub notes: Vec<String>,
+}
+
+impl UploadPreview {
+    fn new(path: &Path, entry: Option<String>) -> Self {
+        UploadPreview {
+            path: path.to_path_buf(),
+            entry,
+            size: 0,
+            format: None,
+            svg: false,
+            dimensions: None,
+            problems: vec![],
+            notes: vec![],
+        }
+    }
+
+    /// 读取文件信息并按 sm.ms 的限制进行校验，不会发起任何网络请求
+    ///
+    /// 压缩包会展开成包内的每一张图片，读取到的内容交给 `f` 用于显示缩略图，
+    /// 压缩包内的图片逐个处理，不会同时读入内存
+    pub fn read_source<T>(
+        path: impl AsRef<Path>,
+        options: &PreprocessOptions,
+        mut f: impl FnMut(Self, Option<Vec<u8>>) -> T,
+    ) -> Vec<T> {
+        let path = path.as_ref();
+        let failed = |problem: String| {
+            let mut preview = Self::new(path, None);
+            preview.problems.push(problem);
+            preview
+        };
+
+        if !archive::is_archive(path) {
+            return vec![match std::fs::read(path) {
+                Ok(bytes) => f(Self::from_bytes(path, None, &bytes, options), Some(bytes)),
+                Err(err) => f(failed(t!("preflight.unreadable", error = err)), None),
+            }];
+        }
+
+        let mut res = vec![];
+        let visited = archive::visit_images(path, |entry, bytes| {
+            let preview = Self::from_bytes(path, Some(entry), &bytes, options);
+            res.push(f(preview, Some(bytes)));
+        });
+        let problem = match visited {
+            Ok(()) if res.is_empty() => t!("preflight.no_images").to_owned(),
+            Ok(()) => return res,
+            Err(err) => t!("preflight.archive_unreadable", error = format!("{:#}", err)),
+        };
+        res.push(f(failed(problem), None));
+        res
+    }
+
+    pub fn from_bytes(
+        path: &Path,
+        entry: Option<String>,
+        bytes: &[u8],
+        options: &PreprocessOptions,
+    ) -> Self {
+        let mut preview = Self::new(path, entry);
+
+        preview.size = bytes.len() as u64;
+        preview.format = image::guess_format(bytes).ok();
+
+        if let Some(format) = preview.format {
+            preview.dimensions =
+                image::io::Reader::with_format(std::io::Cursor::new(bytes), format)
+                    .into_dimensions()
+                    .ok();
+        } else if convert::is_svg(bytes) {
+            preview.svg = true;
+            preview.dimensions = convert::svg_size(bytes).map(|(w, h)| {
+                (
+                    (w as f32 * options.svg_scale) as u32,
+                    (h as f32 * options.svg_scale) as u32,
+                )
+            });
+        }
+
+        preview.check(bytes, options);
+
+        if preview.problems.is_empty() && preview.dimensions.is_none() {
+            preview
+                .problems
+                .push(t!("preflight.no_dimensions").to_owned());
+        }
+
+        preview
+    }
+
+    /// 显示用的名称，压缩包内的文件带上压缩包路径
+    pub fn label(&self) -> String {
+        let path = self.path.display().to_string();
+        match &self.entry {
+            Some(entry) => archive::entry_label(&path, entry),
+            None => path,
+        }
+    }
+
+    /// 上传时默认使用的文件名
+    pub fn filename(&self) -> String {
+        match &self.entry {
+            Some(entry) => archive::entry_filename(entry).to_owned(),
+            None => self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// 例如: "PNG 1920x1080 1.20 MB"
+    pub fn summary(&self) -> String {
+        let format = match self.format {
+            Some(format) => format_name(format),
+            None if self.svg => "SVG",
+            None => t!("preflight.unknown_format"),
+        };
+        let dimensions = self
+            .dimensions
+            .map(|(w, h)| format!("{}x{}", w, h))
+            .unwrap_or_else(|| "?x?".to_owned());
+        format!("{} {} {}", format, dimensions, util::human_bytes(self.size))
+    }
+
+    /// 按 sm.ms 的限制校验大小和格式，预处理能解决的问题记录到 `notes`
+    fn check(&mut self, bytes: &[u8], options: &PreprocessOptions) {
+        let accepted = self
+            .format
+            .is_some_and(|format| K_ACCEPTED_FORMATS.contains(&format));
+        let convertible = self.svg || self.format.is_some_and(|format| format.can_read());
+        let target = options.output_format.image_format();
+
+        if !accepted && !convertible {
+            match self.format {
+                Some(format) => self.problems.push(t!(
+                    "preflight.unsupported",
+                    format = format_name(format),
+                    accepted = accepted_formats_text()
+                )),
+                None => self.problems.push(t!(
+                    "preflight.unrecognized",
+                    accepted = accepted_formats_text()
+                )),
+            }
+            return;
+        }
+
+        if self.size == 0 {
+            self.problems.push(t!("preflight.empty").to_owned());
+            return;
+        }
+
+        let converted = !accepted || target.is_some_and(|target| Some(target) != self.format);
+        if converted {
+            let target = target.unwrap_or(ImageFormat::Png);
+            self.notes
+                .push(t!("preflight.convert", format = format_name(target)));
+            // 转换后的大小和原文件不同，按预处理的结果检查是否超出限制
+            if let Err(err) = preprocess::prepare_bytes(bytes.to_vec(), self.filename(), options) {
+                self.problems.push(format!("{:#}", err));
+            }
+            return;
+        }
+
+        if let Some(format) = self.format {
+            match mime::check(bytes, format, &self.filename()) {
+                Ok(Some(fixed)) => self
+                    .notes
+                    .push(t!("preflight.fix_extension", filename = fixed)),
+                Ok(None) => {}
+                Err(err) => self.problems.push(format!("{:#}", err)),
+            }
+        }
+
+        if self.size > K_MAX_UPLOAD_SIZE {
+            if options.compress && self.format != Some(ImageFormat::Gif) {
+                self.notes.push(t!(
+                    "preflight.compress",
+                    limit = util::human_bytes(K_MAX_UPLOAD_SIZE)
+                ));
+            } else {
+                self.problems.push(t!(
+                    "preflight.too_large",
+                    size = util::human_bytes(self.size),
+                    limit = util::human_bytes(K_MAX_UPLOAD_SIZE)
+                ));
+            }
+        }
+    }
+}
+
+pub fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "JPEG",
+        ImageFormat::Png => "PNG",
+        ImageFormat::Gif => "GIF",
+        ImageFormat::Bmp => "BMP",
+        ImageFormat::WebP => "WebP",
+        ImageFormat::Tiff => "TIFF",
+        ImageFormat::Avif => "AVIF",
+        ImageFormat::Ico => "ICO",
+        ImageFormat::Tga => "TGA",
+        ImageFormat::Hdr => "HDR",
+        ImageFormat::OpenExr => "OpenEXR",
+        ImageFormat::Pnm => "PNM",
+        ImageFormat::Dds => "DDS",
+        ImageFormat::Farbfeld => "Farbfeld",
+        _ => t!("preflight.unknown_format"),
+    }
+}
+
+fn accepted_formats_text() -> String {
+    K_ACCEPTED_FORMATS
+        .iter()
+        .map(|f| format_name(*f))
+        .collect::<Vec<_>>()
+        .join("/")
+}
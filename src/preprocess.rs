@@ -0,0 +1,475 @@
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
+    imageops::FilterType,
+    ColorType, DynamicImage, GenericImageView, ImageFormat,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    convert::{self, OutputFormat},
+    i18n::t,
+    metadata, mime, preflight,
+    rename::{self, RenameOptions},
+    util,
+    watermark::{self, WatermarkOptions},
+};
+
+/// 压缩时允许降到的最低质量
+const K_MIN_QUALITY: u8 = 40;
+/// 每次降低的质量
+const K_QUALITY_STEP: u8 = 10;
+/// 质量降到最低仍然超出大小时，每次缩小的比例
+const K_DOWNSCALE_RATIO: f32 = 0.75;
+
+/// 上传前的预处理设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreprocessOptions {
+    /// 超出大小或尺寸限制时自动压缩
+    pub compress: bool,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub jpeg_quality: u8,
+    pub webp_quality: u8,
+    /// 移除 EXIF/XMP 等元数据，避免泄露 GPS 位置和设备信息
+    pub strip_metadata: bool,
+    /// 强制转换为指定格式
+    pub output_format: OutputFormat,
+    /// SVG 渲染为位图时的缩放比例
+    pub svg_scale: f32,
+    pub watermark: WatermarkOptions,
+    /// 上传使用的文件名
+    pub rename: RenameOptions,
+}
+
+impl Default for PreprocessOptions {
+    fn default() -> Self {
+        Self {
+            compress: false,
+            max_width: 4096,
+            max_height: 4096,
+            jpeg_quality: 85,
+            webp_quality: 80,
+            strip_metadata: true,
+            output_format: OutputFormat::Auto,
+            svg_scale: 1.0,
+            watermark: Default::default(),
+            rename: Default::default(),
+        }
+    }
+}
+
+/// 预处理后准备上传的数据，原文件不会被修改
+#[derive(Debug)]
+pub struct Prepared {
+    pub bytes: Vec<u8>,
+    pub filename: String,
+    pub format: ImageFormat,
+    pub original_size: u64,
+    /// 对文件做过的处理，例如压缩
+    pub notes: Vec<String>,
+}
+
+impl Prepared {
+    pub fn size(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// 例如: "8.20 MB -> 3.10 MB，节省 62%"
+    pub fn savings(&self) -> Option<String> {
+        if self.size() >= self.original_size {
+            return None;
+        }
+
+        let saved = self.original_size - self.size();
+        Some(t!(
+            "preprocess.savings",
+            from = util::human_bytes(self.original_size),
+            to = util::human_bytes(self.size()),
+            percent = saved * 100 / self.original_size
+        ))
+    }
+}
+
+pub fn prepare_bytes(
+    bytes: Vec<u8>,
+    filename: String,
+    options: &PreprocessOptions,
+) -> anyhow::Result<Prepared> {
+    let mut prepared = convert(bytes, filename, options)?;
+
+    if options.watermark.enabled {
+        add_watermark(&mut prepared, options)?;
+    }
+
+    if options.strip_metadata {
+        strip_metadata(&mut prepared, options)?;
+    }
+
+    if options.compress {
+        compress(&mut prepared, options)?;
+    }
+
+    if prepared.size() > preflight::K_MAX_UPLOAD_SIZE {
+        anyhow::bail!(t!(
+            "preprocess.too_large",
+            size = util::human_bytes(prepared.size()),
+            limit = util::human_bytes(preflight::K_MAX_UPLOAD_SIZE)
+        ));
+    }
+
+    check_mime(&mut prepared)?;
+
+    if let Some(filename) = rename::remote_filename(
+        &prepared.filename,
+        &prepared.bytes,
+        prepared.format,
+        &options.rename,
+    ) {
+        prepared
+            .notes
+            .push(t!("preprocess.renamed", filename = filename));
+        prepared.filename = filename;
+    }
+
+    Ok(prepared)
+}
+
+/// 最后按文件头确认格式，扩展名与内容不符时修正，例如 PNG 命名为 a.jpg
+fn check_mime(prepared: &mut Prepared) -> anyhow::Result<()> {
+    if let Some(filename) = mime::check(&prepared.bytes, prepared.format, &prepared.filename)? {
+        prepared.notes.push(t!(
+            "preprocess.fix_extension",
+            filename = filename,
+            format = preflight::format_name(prepared.format)
+        ));
+        prepared.filename = filename;
+    }
+
+    Ok(())
+}
+
+/// 将 sm.ms 不支持的格式（SVG、TIFF 等）或与指定格式不一致的图片转换为目标格式
+fn convert(
+    bytes: Vec<u8>,
+    filename: String,
+    options: &PreprocessOptions,
+) -> anyhow::Result<Prepared> {
+    let mut prepared = Prepared {
+        original_size: bytes.len() as u64,
+        bytes,
+        filename,
+        format: ImageFormat::Png,
+        notes: vec![],
+    };
+
+    let target = options.output_format.image_format();
+    let (img, source) = if convert::is_svg(&prepared.bytes) {
+        let img = convert::rasterize_svg(&prepared.bytes, options.svg_scale)?;
+        (img, "SVG")
+    } else {
+        let format = image::guess_format(&prepared.bytes)?;
+        let accepted = preflight::K_ACCEPTED_FORMATS.contains(&format);
+        if target.map_or(accepted, |target| target == format) {
+            prepared.format = format;
+            return Ok(prepared);
+        }
+
+        let img = decode(&prepared.bytes, format)?;
+        (img, preflight::format_name(format))
+    };
+
+    let format = target.unwrap_or(ImageFormat::Png);
+    prepared.bytes = encode(&img, format, quality(format, options))?;
+    prepared.format = format;
+    prepared.filename = util::replace_extension(&prepared.filename, format);
+    prepared.notes.push(t!(
+        "preprocess.converted",
+        from = source,
+        to = preflight::format_name(format)
+    ));
+
+    Ok(prepared)
+}
+
+fn add_watermark(prepared: &mut Prepared, options: &PreprocessOptions) -> anyhow::Result<()> {
+    // 重新编码会丢失动画
+    if prepared.format == ImageFormat::Gif {
+        prepared
+            .notes
+            .push(t!("preprocess.gif_watermark").to_owned());
+        return Ok(());
+    }
+
+    let img = decode(&prepared.bytes, prepared.format)?;
+    let img = watermark::apply(&img, &options.watermark)?;
+    prepared.bytes = encode(&img, prepared.format, quality(prepared.format, options))?;
+    prepared.notes.push(t!("preprocess.watermarked").to_owned());
+
+    Ok(())
+}
+
+/// 先按 EXIF 方向旋转图片再移除元数据，避免移除后图片方向错误
+fn strip_metadata(prepared: &mut Prepared, options: &PreprocessOptions) -> anyhow::Result<()> {
+    match metadata::orientation(&prepared.bytes) {
+        Some(orientation) if orientation != 1 => {
+            // 重新编码后不会保留任何元数据
+            let img = decode(&prepared.bytes, prepared.format)?;
+            prepared.bytes = encode(&img, prepared.format, quality(prepared.format, options))?;
+            prepared.notes.push(t!("preprocess.oriented").to_owned());
+        }
+        _ => {
+            let bytes = metadata::strip(&prepared.bytes, prepared.format)?;
+            if bytes.len() != prepared.bytes.len() {
+                prepared.notes.push(t!("preprocess.stripped").to_owned());
+            }
+            prepared.bytes = bytes;
+        }
+    }
+
+    Ok(())
+}
+
+/// 文件大小或尺寸超出限制时，先缩小尺寸再逐步降低质量，直到符合 sm.ms 的大小限制
+fn compress(prepared: &mut Prepared, options: &PreprocessOptions) -> anyhow::Result<()> {
+    let oversize = prepared.size() > preflight::K_MAX_UPLOAD_SIZE;
+    let dimensions =
+        image::io::Reader::with_format(std::io::Cursor::new(&prepared.bytes), prepared.format)
+            .into_dimensions()?;
+    let too_large = exceeds(dimensions, options);
+
+    if !oversize && !too_large {
+        return Ok(());
+    }
+
+    // 重新编码会丢失动画
+    if prepared.format == ImageFormat::Gif {
+        if oversize {
+            anyhow::bail!(t!("preprocess.gif_compress"));
+        }
+        return Ok(());
+    }
+
+    let mut img = decode(&prepared.bytes, prepared.format)?;
+    if too_large {
+        img = img.resize(options.max_width, options.max_height, FilterType::Lanczos3);
+    }
+
+    // 先保留原格式，只缩小尺寸后仍然超出大小时才转换为有损格式
+    let lossy = target_format(prepared.format, &img);
+    let mut format = prepared.format;
+    let mut quality = quality(format, options);
+
+    let bytes = loop {
+        let bytes = encode(&img, format, quality)?;
+        if bytes.len() as u64 <= preflight::K_MAX_UPLOAD_SIZE {
+            break bytes;
+        }
+
+        if format != lossy {
+            format = lossy;
+            quality = self::quality(format, options);
+            continue;
+        }
+
+        if quality > K_MIN_QUALITY {
+            quality = quality.saturating_sub(K_QUALITY_STEP).max(K_MIN_QUALITY);
+            continue;
+        }
+
+        // 质量已经最低，继续缩小尺寸
+        let (w, h) = img.dimensions();
+        if w <= 1 || h <= 1 {
+            anyhow::bail!(t!(
+                "preprocess.compress_failed",
+                limit = util::human_bytes(preflight::K_MAX_UPLOAD_SIZE)
+            ));
+        }
+        img = img.resize(
+            (w as f32 * K_DOWNSCALE_RATIO) as u32,
+            (h as f32 * K_DOWNSCALE_RATIO) as u32,
+            FilterType::Triangle,
+        );
+    };
+
+    let (w, h) = img.dimensions();
+    prepared.bytes = bytes;
+    prepared.format = format;
+    prepared.filename = util::replace_extension(&prepared.filename, format);
+    if format == lossy {
+        prepared.notes.push(t!(
+            "preprocess.compressed",
+            format = preflight::format_name(format),
+            width = w,
+            height = h,
+            quality = quality
+        ));
+    } else {
+        prepared.notes.push(t!(
+            "preprocess.resized",
+            format = preflight::format_name(format),
+            width = w,
+            height = h
+        ));
+    }
+    if let Some(savings) = prepared.savings() {
+        prepared.notes.push(savings);
+    }
+
+    Ok(())
+}
+
+/// 解码图片并按 EXIF 方向旋转
+fn decode(bytes: &[u8], format: ImageFormat) -> anyhow::Result<DynamicImage> {
+    let img = image::load_from_memory_with_format(bytes, format)?;
+
+    Ok(match metadata::orientation(bytes) {
+        Some(orientation) => metadata::apply_orientation(img, orientation),
+        None => img,
+    })
+}
+
+fn quality(format: ImageFormat, options: &PreprocessOptions) -> u8 {
+    match format {
+        ImageFormat::WebP => options.webp_quality,
+        _ => options.jpeg_quality,
+    }
+}
+
+fn exceeds((w, h): (u32, u32), options: &PreprocessOptions) -> bool {
+    w > options.max_width || h > options.max_height
+}
+
+/// 原格式放不下时使用的有损格式，有透明通道的图片压缩为 WebP，其它压缩为 JPEG
+fn target_format(format: ImageFormat, img: &DynamicImage) -> ImageFormat {
+    if format == ImageFormat::WebP || img.color().has_alpha() {
+        ImageFormat::WebP
+    } else {
+        ImageFormat::Jpeg
+    }
+}
+
+/// JPEG 不支持透明通道，透明的部分合成到白色背景上，直接丢弃透明通道会变成黑色
+fn flatten(img: &DynamicImage) -> image::RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+
+    let rgba = img.to_rgba8();
+    image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+pub fn encode(img: &DynamicImage, format: ImageFormat, quality: u8) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let (w, h) = img.dimensions();
+
+    match format {
+        ImageFormat::Jpeg => {
+            let rgb = flatten(img);
+            JpegEncoder::new_with_quality(&mut bytes, quality).encode(
+                rgb.as_raw(),
+                w,
+                h,
+                ColorType::Rgb8,
+            )?;
+        }
+        ImageFormat::WebP => {
+            let rgba = img.to_rgba8();
+            WebPEncoder::new_with_quality(&mut bytes, WebPQuality::lossy(quality)).encode(
+                rgba.as_raw(),
+                w,
+                h,
+                ColorType::Rgba8,
+            )?;
+        }
+        _ => img.write_to(&mut std::io::Cursor::new(&mut bytes), format)?,
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn compress_options() -> PreprocessOptions {
+        PreprocessOptions {
+            compress: true,
+            ..Default::default()
+        }
+    }
+
+    /// 噪点图片几乎无法无损压缩，用来制造超出大小限制的文件
+    fn image(w: u32, h: u32, noise: bool, format: ImageFormat) -> Vec<u8> {
+        let img = RgbImage::from_fn(w, h, |x, y| {
+            if noise {
+                let v =
+                    (x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)).wrapping_mul(2246822519);
+                Rgb([v as u8, (v >> 8) as u8, (v >> 16) as u8])
+            } else {
+                Rgb([(x % 256) as u8, 0, 0])
+            }
+        });
+        encode(&DynamicImage::ImageRgb8(img), format, 90).unwrap()
+    }
+
+    #[test]
+    fn small_image_is_unchanged() {
+        let bytes = image(64, 64, false, ImageFormat::Png);
+        let prepared =
+            prepare_bytes(bytes.clone(), "a.png".to_owned(), &compress_options()).unwrap();
+        assert_eq!(prepared.bytes, bytes);
+        assert_eq!(prepared.filename, "a.png");
+        assert!(prepared.notes.is_empty());
+        assert_eq!(prepared.savings(), None);
+    }
+
+    #[test]
+    fn resize_keeps_source_format() {
+        let bytes = image(5000, 100, false, ImageFormat::Png);
+        let prepared = prepare_bytes(bytes, "a.png".to_owned(), &compress_options()).unwrap();
+        assert_eq!(prepared.format, ImageFormat::Png);
+        assert_eq!(prepared.filename, "a.png");
+        let img = image::load_from_memory(&prepared.bytes).unwrap();
+        assert_eq!(img.dimensions(), (4096, 82));
+    }
+
+    #[test]
+    fn oversized_png_falls_back_to_lossy() {
+        let bytes = image(1400, 1400, true, ImageFormat::Png);
+        assert!(bytes.len() as u64 > preflight::K_MAX_UPLOAD_SIZE);
+
+        let prepared = prepare_bytes(bytes, "a.png".to_owned(), &compress_options()).unwrap();
+        assert_eq!(prepared.format, ImageFormat::Jpeg);
+        assert_eq!(prepared.filename, "a.jpg");
+        assert!(prepared.size() <= preflight::K_MAX_UPLOAD_SIZE);
+        // 压缩说明和节省的大小
+        assert_eq!(prepared.notes.len(), 2);
+        assert_eq!(prepared.notes[1], prepared.savings().unwrap());
+    }
+
+    #[test]
+    fn oversized_without_compress_is_rejected() {
+        let bytes = image(1400, 1400, true, ImageFormat::Png);
+        let options = PreprocessOptions::default();
+        assert!(prepare_bytes(bytes, "a.png".to_owned(), &options).is_err());
+    }
+
+    #[test]
+    fn oversized_gif_is_rejected() {
+        let mut bytes = image(16, 16, false, ImageFormat::Gif);
+        // GIF 结束后的数据不会被读取，只用来超出大小限制
+        bytes.resize(preflight::K_MAX_UPLOAD_SIZE as usize + 1, 0);
+        let err = prepare_bytes(bytes, "a.gif".to_owned(), &compress_options()).unwrap_err();
+        assert_eq!(err.to_string(), t!("preprocess.gif_compress"));
+    }
+}
@@ -0,0 +1,160 @@
+use time::{Date, Month, OffsetDateTime};
+
+use crate::{api, i18n::t, util};
+
+/// 使用超过这个比例时用红色显示
+pub const K_QUOTA_WARNING_RATIO: f32 = 0.9;
+
+/// 存储空间的使用情况
+pub struct Quota {
+    /// 0 到 1 之间
+    pub ratio: f32,
+    pub remaining: u64,
+}
+
+impl Quota {
+    pub fn new(profile: &api::ProfileData) -> Self {
+        let usage = profile.disk_usage_raw as u64;
+        let limit = profile.disk_limit_raw as u64;
+        let ratio = if limit == 0 {
+            1.0
+        } else {
+            (usage as f64 / limit as f64).min(1.0) as f32
+        };
+        Self {
+            ratio,
+            remaining: limit.saturating_sub(usage),
+        }
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.ratio >= K_QUOTA_WARNING_RATIO
+    }
+
+    /// 例如 "剩余 4.50 GB (10%)"
+    pub fn remaining_text(&self) -> String {
+        t!(
+            "profile.remaining",
+            size = util::human_bytes(self.remaining),
+            percent = ((1.0 - self.ratio) * 100.0).round() as u32,
+        )
+    }
+}
+
+/// 用户组到期时间
+pub enum GroupExpiry {
+    /// 剩余天数，0 表示今天到期
+    Days(i64),
+    Expired,
+}
+
+impl GroupExpiry {
+    /// `group_expire` 为 "2024-01-31" 这样的日期，免费用户为 "0000-00-00" 或者空
+    pub fn new(profile: &api::ProfileData, now: OffsetDateTime) -> Option<Self> {
+        let date = parse_date(&profile.group_expire)?;
+        let days = (date - now.date()).whole_days();
+        Some(if days < 0 {
+            GroupExpiry::Expired
+        } else {
+            GroupExpiry::Days(days)
+        })
+    }
+
+    pub fn is_soon(&self) -> bool {
+        match self {
+            GroupExpiry::Days(days) => *days <= 7,
+            GroupExpiry::Expired => true,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            GroupExpiry::Days(0) => t!("profile.expire_today").to_owned(),
+            GroupExpiry::Days(days) => t!("profile.expire_in", days = days),
+            GroupExpiry::Expired => t!("profile.expired").to_owned(),
+        }
+    }
+}
+
+/// 只取日期部分，忽略可能带有的时间
+fn parse_date(text: &str) -> Option<Date> {
+    let mut parts = text.get(..10)?.split('-');
+    let year = parts.next()?.parse().ok()?;
+    let month: u8 = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(usage: usize, limit: usize, group_expire: &str) -> api::ProfileData {
+        api::ProfileData {
+            username: "user".to_owned(),
+            email: "user@example.com".to_owned(),
+            role: "user".to_owned(),
+            group_expire: group_expire.to_owned(),
+            email_verified: 1,
+            disk_usage: String::new(),
+            disk_limit: String::new(),
+            disk_usage_raw: usage,
+            disk_limit_raw: limit,
+        }
+    }
+
+    #[test]
+    fn parse_date_ignores_time() {
+        let date = Date::from_calendar_date(2024, Month::January, 31).unwrap();
+        assert_eq!(parse_date("2024-01-31"), Some(date));
+        assert_eq!(parse_date("2024-01-31 12:30:00"), Some(date));
+    }
+
+    #[test]
+    fn parse_date_rejects_invalid() {
+        assert_eq!(parse_date(""), None);
+        assert_eq!(parse_date("0000-00-00"), None);
+        assert_eq!(parse_date("2024-02-30"), None);
+        assert_eq!(parse_date("2024/01/31"), None);
+    }
+
+    #[test]
+    fn quota_ratio_and_remaining() {
+        let quota = Quota::new(&profile(90, 100, ""));
+        assert_eq!(quota.ratio, 0.9);
+        assert_eq!(quota.remaining, 10);
+        assert!(quota.is_low());
+
+        let quota = Quota::new(&profile(50, 100, ""));
+        assert!(!quota.is_low());
+    }
+
+    #[test]
+    fn quota_handles_zero_and_overused_limit() {
+        let quota = Quota::new(&profile(0, 0, ""));
+        assert_eq!(quota.ratio, 1.0);
+        assert_eq!(quota.remaining, 0);
+
+        let quota = Quota::new(&profile(150, 100, ""));
+        assert_eq!(quota.ratio, 1.0);
+        assert_eq!(quota.remaining, 0);
+    }
+
+    #[test]
+    fn group_expiry_days() {
+        let now = Date::from_calendar_date(2024, Month::January, 24)
+            .unwrap()
+            .with_hms(23, 0, 0)
+            .unwrap()
+            .assume_utc();
+        let expiry = |date| GroupExpiry::new(&profile(0, 0, date), now);
+
+        assert!(expiry("0000-00-00").is_none());
+        assert!(matches!(expiry("2024-01-24"), Some(GroupExpiry::Days(0))));
+        assert!(matches!(expiry("2024-01-31"), Some(GroupExpiry::Days(7))));
+        assert!(matches!(expiry("2024-02-01"), Some(GroupExpiry::Days(8))));
+        assert!(matches!(expiry("2024-01-23"), Some(GroupExpiry::Expired)));
+        assert!(expiry("2024-01-31").unwrap().is_soon());
+        assert!(!expiry("2024-02-01").unwrap().is_soon());
+    }
+}
@@ -0,0 +1,635 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::{
+    api, archive, config,
+    dedup::{self, SharedDedupIndex},
+    i18n::t,
+    mime, preflight,
+    preprocess::{self, PreprocessOptions},
+    remote,
+    rename::{self, RenamePolicy},
+    util,
+};
+
+/// 默认同时上传的数量
+pub const K_DEFAULT_CONCURRENCY: usize = 3;
+
+/// 上传队列保存在配置目录中，重启后继续上传
+pub const K_QUEUE_FILE: &str = "sm_ms_queue.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UploadStatus {
+    Pending,
+    Uploading,
+    Done,
+    /// 相同内容之前已经上传过
+    Duplicate,
+    Failed,
+}
+
+impl UploadStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            UploadStatus::Pending => t!("queue.status.pending"),
+            UploadStatus::Uploading => t!("queue.status.uploading"),
+            UploadStatus::Done => t!("queue.status.done"),
+            UploadStatus::Duplicate => t!("queue.status.duplicate"),
+            UploadStatus::Failed => t!("queue.status.failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub id: u64,
+    /// 本地文件路径或者远程图片的 url
+    pub path: String,
+    /// 压缩包内的文件路径，此时 `path` 是压缩包
+    #[serde(default)]
+    pub entry: Option<String>,
+    /// 上传使用的文件名，优先于 `options.rename`
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// 加入队列时的预处理设置
+    #[serde(default)]
+    pub options: PreprocessOptions,
+    /// 加入队列时的账号，只使用这个账号的 token 上传；旧版本的队列为 None
+    #[serde(default)]
+    pub account: Option<String>,
+    pub status: UploadStatus,
+    pub url: Option<String>,
+    /// sm.ms 返回的图片 hash
+    pub hash: Option<String>,
+    /// 失败原因或者预处理的记录
+    pub messages: Vec<String>,
+
+    /// 内存中的图片，例如从标准输入读取的数据，此时 `path` 是文件名；不会保存到队列文件
+    #[serde(skip)]
+    bytes: Option<Arc<Vec<u8>>>,
+    /// 已经交给 tokio 执行，避免重复调度
+    #[serde(skip)]
+    scheduled: bool,
+}
+
+/// 待上传图片的来源
+pub enum Source {
+    File(String),
+    /// 压缩包内的文件
+    Archive {
+        path: String,
+        entry: String,
+    },
+    /// 远程图片，上传前下载
+    Url(String),
+    Bytes {
+        filename: String,
+        bytes: Arc<Vec<u8>>,
+    },
+}
+
+/// 队列中各个状态的数量
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueSummary {
+    pub pending: usize,
+    pub uploading: usize,
+    pub done: usize,
+    pub duplicate: usize,
+    pub failed: usize,
+}
+
+impl QueueSummary {
+    pub fn total(&self) -> usize {
+        self.pending + self.uploading + self.done + self.duplicate + self.failed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pending == 0 && self.uploading == 0
+    }
+}
+
+/// 上传单个文件的结果
+pub enum Outcome {
+    Uploaded {
+        url: String,
+        hash: String,
+        notes: Vec<String>,
+    },
+    /// 本地记录的 hash，sm.ms 返回 image_repeated 时不知道 hash
+    Duplicate { url: String, hash: Option<String> },
+}
+
+/// 状态变化时的回调，例如通知界面刷新
+pub type OnChange = Arc<dyn Fn() + Send + Sync>;
+
+/// 清单中的一条记录，本地路径对应的上传结果
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry<'a> {
+    pub path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<&'a str>,
+    pub status: UploadStatus,
+    pub url: Option<&'a str>,
+    pub error: Option<&'a str>,
+}
+
+/// 所有批次共用的并发限制
+struct Limiter {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(K_DEFAULT_CONCURRENCY)),
+            permits: K_DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+/// 批量上传队列，在已有的 tokio `Runtime` 上按并发数上传
+#[derive(Clone, Default)]
+pub struct UploadQueue {
+    items: Arc<Mutex<Vec<QueueItem>>>,
+    /// 是否保存到 `K_QUEUE_FILE`，命令行使用的临时队列不保存
+    persistent: bool,
+    /// 各个账号上传使用的 token，没有 token 的账号的文件等待重新登录
+    tokens: Arc<Mutex<HashMap<Option<String>, String>>>,
+    /// token 失效的账号，读取后清除
+    expired: Arc<Mutex<HashSet<Option<String>>>>,
+    /// 多次调用 `start` 时同时上传的总数也不超过设置
+    limiter: Arc<Mutex<Limiter>>,
+    /// 还没写入文件的最新队列，多个任务同时保存时只写最后一份
+    unsaved: Arc<Mutex<Option<Vec<u8>>>>,
+    /// 同时只有一个任务写文件
+    writing: Arc<Mutex<()>>,
+}
+
+impl UploadQueue {
+    /// 读取上次保存的队列，中断的上传重新进入等待状态
+    pub fn load() -> Self {
+        let mut items: Vec<QueueItem> = std::fs::read(config::path(K_QUEUE_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        for item in items.iter_mut() {
+            if item.status == UploadStatus::Uploading {
+                item.status = UploadStatus::Pending;
+            }
+        }
+
+        Self {
+            items: Arc::new(Mutex::new(items)),
+            persistent: true,
+            ..Default::default()
+        }
+    }
+
+    /// `filename` 不为空时代替原文件名上传，文件会上传到 `account`
+    pub fn add(
+        &self,
+        path: String,
+        entry: Option<String>,
+        filename: Option<String>,
+        options: PreprocessOptions,
+        account: Option<String>,
+    ) {
+        self.push(path, entry, None, filename, options, account);
+    }
+
+    /// 上传内存中的图片，`name` 是显示和默认上传使用的文件名
+    pub fn add_bytes(
+        &self,
+        name: String,
+        bytes: Vec<u8>,
+        filename: Option<String>,
+        options: PreprocessOptions,
+        account: Option<String>,
+    ) {
+        self.push(
+            name,
+            None,
+            Some(Arc::new(bytes)),
+            filename,
+            options,
+            account,
+        );
+    }
+
+    fn push(
+        &self,
+        path: String,
+        entry: Option<String>,
+        bytes: Option<Arc<Vec<u8>>>,
+        filename: Option<String>,
+        options: PreprocessOptions,
+        account: Option<String>,
+    ) {
+        let mut items = self.items.lock().unwrap();
+        let id = items.iter().map(|item| item.id + 1).max().unwrap_or(0);
+        items.push(QueueItem {
+            id,
+            path,
+            entry,
+            filename,
+            options,
+            account,
+            status: UploadStatus::Pending,
+            url: None,
+            hash: None,
+            messages: vec![],
+            bytes,
+            scheduled: false,
+        });
+        self.save(items);
+    }
+
+    pub fn items(&self) -> Vec<QueueItem> {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn summary(&self) -> QueueSummary {
+        let mut summary = QueueSummary::default();
+        for item in self.items.lock().unwrap().iter() {
+            match item.status {
+                UploadStatus::Pending => summary.pending += 1,
+                UploadStatus::Uploading => summary.uploading += 1,
+                UploadStatus::Done => summary.done += 1,
+                UploadStatus::Duplicate => summary.duplicate += 1,
+                UploadStatus::Failed => summary.failed += 1,
+            }
+        }
+        summary
+    }
+
+    /// 失败的文件重新进入等待状态
+    pub fn retry_failed(&self) {
+        let mut items = self.items.lock().unwrap();
+        for item in items.iter_mut() {
+            if item.status == UploadStatus::Failed {
+                reset(item);
+            }
+        }
+        self.save(items);
+    }
+
+    pub fn retry(&self, id: u64) {
+        self.update(id, |item| {
+            if item.status == UploadStatus::Failed {
+                reset(item);
+            }
+        });
+    }
+
+    /// 移除已经上传成功或重复的文件
+    pub fn clear_finished(&self) {
+        let mut items = self.items.lock().unwrap();
+        items.retain(|item| !matches!(item.status, UploadStatus::Done | UploadStatus::Duplicate));
+        self.save(items);
+    }
+
+    /// 把上传结果写入 JSON 清单
+    pub fn write_manifest(&self, path: &Path) -> anyhow::Result<()> {
+        let items = self.items.lock().unwrap();
+        let entries: Vec<ManifestEntry> = items
+            .iter()
+            .map(|item| ManifestEntry {
+                path: &item.path,
+                entry: item.entry.as_deref(),
+                status: item.status,
+                url: item.url.as_deref(),
+                error: (item.status == UploadStatus::Failed)
+                    .then(|| item.messages.first().map(String::as_str))
+                    .flatten(),
+            })
+            .collect();
+
+        Ok(std::fs::write(path, serde_json::to_vec_pretty(&entries)?)?)
+    }
+
+    /// `account` 的 token 失效后返回一次 true，失效时正在上传的文件会回到等待状态
+    pub fn take_expired(&self, account: Option<&str>) -> bool {
+        self.expired
+            .lock()
+            .unwrap()
+            .remove(&account.map(str::to_owned))
+    }
+
+    /// 已经使用新的 token 重新登录时不处理
+    fn expire(&self, account: &Option<String>, token: &str) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.get(account).map(String::as_str) == Some(token) {
+            tokens.remove(account);
+            self.expired.lock().unwrap().insert(account.clone());
+        }
+    }
+
+    /// 退出登录后 `account` 的文件不再上传，重新登录后继续
+    pub fn stop(&self, account: Option<&str>) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .remove(&account.map(str::to_owned));
+        archive::clear_cache();
+    }
+
+    /// 开始上传 `account` 等待中的文件，所有账号合计最多同时上传 `concurrency` 个
+    ///
+    /// 其他账号的文件不受影响，等对应的账号登录后再上传；旧版本队列中没有账号的文件归 `account`
+    pub fn start(
+        &self,
+        handle: &tokio::runtime::Handle,
+        account: Option<String>,
+        token: String,
+        dedup: SharedDedupIndex,
+        concurrency: usize,
+        on_change: OnChange,
+    ) {
+        self.tokens.lock().unwrap().insert(account.clone(), token);
+        let semaphore = self.set_concurrency(handle, concurrency);
+
+        let ids: Vec<u64> = {
+            let mut items = self.items.lock().unwrap();
+            let ids: Vec<u64> = items
+                .iter_mut()
+                .filter(|item| item.status == UploadStatus::Pending && !item.scheduled)
+                .filter(|item| item.account.is_none() || item.account == account)
+                .map(|item| {
+                    item.account = account.clone();
+                    item.scheduled = true;
+                    item.id
+                })
+                .collect();
+            self.save(items);
+            ids
+        };
+
+        for id in ids {
+            let queue = self.clone();
+            let account = account.clone();
+            let semaphore = semaphore.clone();
+            let dedup = dedup.clone();
+            let on_change = on_change.clone();
+
+            handle.spawn(async move {
+                let Ok(_permit) = semaphore.acquire_owned().await else {
+                    return;
+                };
+
+                // token 已失效或者已经退出登录时留在等待状态，重新登录后再上传
+                let token = queue.tokens.lock().unwrap().get(&account).cloned();
+                let Some(token) = token else {
+                    queue.update(id, |item| item.scheduled = false);
+                    return;
+                };
+
+                let Some((source, filename, options)) = queue.update(id, |item| {
+                    item.status = UploadStatus::Uploading;
+                    (item.source(), item.filename.clone(), item.options.clone())
+                }) else {
+                    return;
+                };
+                on_change();
+
+                let res = process(&token, source, filename, options, &dedup).await;
+                let unauthorized = res.as_ref().is_err_and(api::is_unauthorized);
+                queue.update(id, |item| {
+                    item.scheduled = false;
+                    match res {
+                        Ok(Outcome::Uploaded { url, hash, notes }) => {
+                            item.status = UploadStatus::Done;
+                            item.url = Some(url);
+                            item.hash = Some(hash);
+                            item.messages = notes;
+                        }
+                        Ok(Outcome::Duplicate { url, hash }) => {
+                            item.status = UploadStatus::Duplicate;
+                            item.url = Some(url);
+                            item.hash = hash;
+                        }
+                        Err(err) if unauthorized => {
+                            item.status = UploadStatus::Pending;
+                            item.messages = vec![err.to_string()];
+                        }
+                        Err(err) => {
+                            item.status = UploadStatus::Failed;
+                            item.messages = vec![err.to_string()];
+                        }
+                    }
+                });
+                if unauthorized {
+                    queue.expire(&account, &token);
+                }
+                on_change();
+            });
+        }
+    }
+
+    /// 修改并发数，减少时等正在上传的文件完成后生效
+    fn set_concurrency(
+        &self,
+        handle: &tokio::runtime::Handle,
+        concurrency: usize,
+    ) -> Arc<Semaphore> {
+        let concurrency = concurrency.max(1);
+        let mut limiter = self.limiter.lock().unwrap();
+        if concurrency > limiter.permits {
+            limiter.semaphore.add_permits(concurrency - limiter.permits);
+        } else if concurrency < limiter.permits {
+            // 取走多余的许可后不再归还
+            let semaphore = limiter.semaphore.clone();
+            let excess = (limiter.permits - concurrency) as u32;
+            handle.spawn(async move {
+                if let Ok(permits) = semaphore.acquire_many_owned(excess).await {
+                    permits.forget();
+                }
+            });
+        }
+        limiter.permits = concurrency;
+        limiter.semaphore.clone()
+    }
+
+    /// 修改一个文件的状态并保存队列
+    fn update<R>(&self, id: u64, f: impl FnOnce(&mut QueueItem) -> R) -> Option<R> {
+        let mut items = self.items.lock().unwrap();
+        let res = items.iter_mut().find(|item| item.id == id).map(f);
+        self.save(items);
+        res
+    }
+
+    /// 在持有锁的时候取出队列内容，释放锁后再写文件，界面读取队列时不用等待磁盘
+    fn save(&self, items: MutexGuard<Vec<QueueItem>>) {
+        if !self.persistent {
+            return;
+        }
+
+        // 内存中的图片无法恢复，不保存
+        let saved: Vec<&QueueItem> = items.iter().filter(|item| item.bytes.is_none()).collect();
+        match serde_json::to_vec(&saved) {
+            Ok(bytes) => *self.unsaved.lock().unwrap() = Some(bytes),
+            Err(err) => {
+                dbg!(err);
+                return;
+            }
+        }
+        drop(items);
+
+        let _writing = self.writing.lock().unwrap();
+        // 等待期间其它任务已经写入了更新的内容
+        let Some(bytes) = self.unsaved.lock().unwrap().take() else {
+            return;
+        };
+        if let Err(err) = util::write_atomic(&config::path(K_QUEUE_FILE), &bytes) {
+            dbg!(err);
+        }
+    }
+}
+
+impl QueueItem {
+    /// 显示用的名称，压缩包内的文件带上压缩包路径
+    pub fn label(&self) -> String {
+        self.source().label()
+    }
+
+    pub fn source(&self) -> Source {
+        if let Some(bytes) = &self.bytes {
+            return Source::Bytes {
+                filename: self.path.clone(),
+                bytes: bytes.clone(),
+            };
+        }
+
+        match &self.entry {
+            Some(entry) => Source::Archive {
+                path: self.path.clone(),
+                entry: entry.clone(),
+            },
+            None if remote::is_url(&self.path) => Source::Url(self.path.clone()),
+            None => Source::File(self.path.clone()),
+        }
+    }
+}
+
+impl Source {
+    pub fn label(&self) -> String {
+        match self {
+            Source::File(path) | Source::Url(path) => path.clone(),
+            Source::Archive { path, entry } => archive::entry_label(path, entry),
+            Source::Bytes { filename, .. } => filename.clone(),
+        }
+    }
+
+    /// 读取图片内容和文件名，远程图片会先下载并校验
+    async fn load(self, options: &PreprocessOptions) -> anyhow::Result<(Vec<u8>, String)> {
+        match self {
+            Source::File(path) => {
+                let Some(filename) = Path::new(&path).file_name().and_then(|name| name.to_str())
+                else {
+                    anyhow::bail!(t!("queue.invalid_filename", path = path));
+                };
+                Ok((tokio::fs::read(&path).await?, filename.to_owned()))
+            }
+            Source::Archive { path, entry } => {
+                let filename = archive::entry_filename(&entry).to_owned();
+                let bytes = tokio::task::spawn_blocking(move || {
+                    archive::read_entry(Path::new(&path), &entry)
+                })
+                .await??;
+                Ok((bytes, filename))
+            }
+            Source::Url(url) => {
+                let (bytes, filename) = remote::download(&url).await?;
+                let preview =
+                    preflight::UploadPreview::from_bytes(Path::new(&url), None, &bytes, options);
+                if !preview.is_ok() {
+                    anyhow::bail!(preview.problems.join("; "));
+                }
+                Ok((bytes, filename))
+            }
+            Source::Bytes { filename, bytes } => {
+                let bytes = Arc::try_unwrap(bytes).unwrap_or_else(|bytes| (*bytes).clone());
+                Ok((bytes, filename))
+            }
+        }
+    }
+}
+
+fn reset(item: &mut QueueItem) {
+    item.status = UploadStatus::Pending;
+    item.messages.clear();
+}
+
+/// 预处理并上传一个文件，相同内容已经上传过时直接返回之前的链接
+///
+/// `filename` 不为空时代替原文件名上传，不再按 `options.rename` 重命名
+pub async fn process(
+    token: &str,
+    source: Source,
+    filename: Option<String>,
+    mut options: PreprocessOptions,
+    dedup: &SharedDedupIndex,
+) -> anyhow::Result<Outcome> {
+    let label = source.label();
+    let (bytes, original) = source.load(&options).await?;
+    let filename = match filename {
+        Some(filename) => {
+            options.rename.policy = RenamePolicy::Original;
+            rename::sanitize(&filename)
+        }
+        None => original,
+    };
+    let prepared =
+        tokio::task::spawn_blocking(move || preprocess::prepare_bytes(bytes, filename, &options))
+            .await??;
+
+    let content_hash = dedup::DedupIndex::content_hash(&prepared.bytes);
+    let entry = dedup.lock().unwrap().get(&content_hash).cloned();
+    // 在 sm.ms 上删除的图片会在删除或者完整同步时从记录中移除，这里不再联网确认
+    if let Some(entry) = entry {
+        return Ok(Outcome::Duplicate {
+            url: entry.url,
+            hash: Some(entry.hash),
+        });
+    }
+
+    let mime = mime::mime_type(prepared.format);
+    let data = match api::upload(token, prepared.bytes, prepared.filename, mime).await {
+        Ok(data) => data,
+        Err(err) => {
+            return match api::repeated_url(&err) {
+                Some(url) => Ok(Outcome::Duplicate {
+                    url: url.to_owned(),
+                    hash: None,
+                }),
+                None => Err(err),
+            }
+        }
+    };
+
+    dedup.lock().unwrap().insert(
+        content_hash,
+        dedup::DedupEntry {
+            path: label,
+            hash: data.hash.clone(),
+            url: data.url.clone(),
+        },
+    );
+    let dedup = dedup.clone();
+    if let Err(err) = tokio::task::spawn_blocking(move || dedup::DedupIndex::save(&dedup)).await? {
+        dbg!(err);
+    }
+
+    Ok(Outcome::Uploaded {
+        url: data.url,
+        hash: data.hash,
+        notes: prepared.notes,
+    })
+}
@@ -0,0 +1,76 @@
+use anyhow::Context;
+
+use crate::{i18n::t, rename, util};
+
+pub fn is_url(text: &str) -> bool {
+    text.starts_with("http://") || text.starts_with("https://")
+}
+
+/// 下载远程图片，返回内容和文件名
+///
+/// 只接受 image/* 的 content-type
+pub async fn download(url: &str) -> anyhow::Result<(Vec<u8>, String)> {
+    let mut res = reqwest::Client::new()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if !content_type.starts_with("image/") {
+        anyhow::bail!(t!(
+            "remote.not_image",
+            content_type = format!("{:?}", content_type)
+        ));
+    }
+
+    if res
+        .content_length()
+        .is_some_and(|len| len > util::K_MAX_INPUT_SIZE)
+    {
+        anyhow::bail!(t!("remote.too_large"));
+    }
+
+    let mut bytes = vec![];
+    while let Some(chunk) = res.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() as u64 > util::K_MAX_INPUT_SIZE {
+            anyhow::bail!(t!("remote.too_large"));
+        }
+    }
+
+    let filename = filename(res.url(), &content_type).context(t!("remote.no_filename"))?;
+    Ok((bytes, filename))
+}
+
+/// 使用 url 的最后一段作为文件名，没有扩展名时根据 content-type 补上
+///
+/// 最后一段是百分号编码的，解码后可能包含 `/` 等字符，需要再替换掉
+fn filename(url: &reqwest::Url, content_type: &str) -> Option<String> {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|name| percent_encoding::percent_decode_str(name).decode_utf8_lossy())
+        .filter(|name| !name.is_empty())
+        .map(|name| rename::sanitize(&name))
+        .unwrap_or_else(|| "image".to_owned());
+
+    if std::path::Path::new(&name).extension().is_some() {
+        return Some(name);
+    }
+
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    let ext = if mime == "image/svg+xml" {
+        "svg"
+    } else {
+        image::ImageFormat::from_mime_type(mime)?
+            .extensions_str()
+            .first()?
+    };
+    Some(format!("{}.{}", name, ext))
+}
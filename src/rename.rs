@@ -0,0 +1,211 @@
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::{dedup, i18n::t, util};
+
+/// 默认的文件名模板，例如 20230501-120000
+pub const K_DEFAULT_TEMPLATE: &str = "{yyyy}{MM}{dd}-{HH}{mm}{ss}";
+
+/// 上传到 sm.ms 时使用的文件名，避免泄露本地文件名
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum RenamePolicy {
+    /// 保留原文件名
+    #[default]
+    Original,
+    /// 随机 UUID
+    Uuid,
+    /// 文件内容的 sha256
+    Hash,
+    /// 按模板生成，例如日期
+    Template,
+}
+
+impl RenamePolicy {
+    pub const ALL: [RenamePolicy; 4] = [
+        RenamePolicy::Original,
+        RenamePolicy::Uuid,
+        RenamePolicy::Hash,
+        RenamePolicy::Template,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RenamePolicy::Original => t!("rename.original"),
+            RenamePolicy::Uuid => t!("rename.uuid"),
+            RenamePolicy::Hash => t!("rename.hash"),
+            RenamePolicy::Template => t!("rename.template"),
+        }
+    }
+}
+
+/// 文件名设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenameOptions {
+    pub policy: RenamePolicy,
+    /// 支持 {name} {hash} {uuid} {yyyy} {MM} {dd} {HH} {mm} {ss}
+    pub template: String,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            policy: RenamePolicy::Original,
+            template: K_DEFAULT_TEMPLATE.to_owned(),
+        }
+    }
+}
+
+/// 按规则生成上传使用的文件名，扩展名由 `format` 决定；保留原文件名时返回 None
+pub fn remote_filename(
+    filename: &str,
+    bytes: &[u8],
+    format: ImageFormat,
+    options: &RenameOptions,
+) -> Option<String> {
+    let name = match options.policy {
+        RenamePolicy::Original => return None,
+        RenamePolicy::Uuid => uuid(),
+        RenamePolicy::Hash => short_hash(bytes),
+        RenamePolicy::Template => render(&options.template, filename, bytes),
+    };
+
+    let name = sanitize(&name);
+    let name = if name.trim().is_empty() { uuid() } else { name };
+    // 模板生成的名字可能包含 '.'，直接追加扩展名
+    let ext = format.extensions_str().first().copied().unwrap_or_default();
+    Some(format!("{}.{}", name, ext))
+}
+
+/// 替换路径分隔符和控制字符，模板、用户填写的文件名和解码后的 url 中都可能出现
+pub fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+fn uuid() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+fn short_hash(bytes: &[u8]) -> String {
+    dedup::DedupIndex::content_hash(bytes)[..16].to_owned()
+}
+
+/// 无法确定本地时区时使用 UTC
+fn render(template: &str, filename: &str, bytes: &[u8]) -> String {
+    let now = util::now_local();
+    let stem = std::path::Path::new(filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut name = template
+        .replace("{name}", &stem)
+        .replace("{yyyy}", &format!("{:04}", now.year()))
+        .replace("{MM}", &format!("{:02}", u8::from(now.month())))
+        .replace("{dd}", &format!("{:02}", now.day()))
+        .replace("{HH}", &format!("{:02}", now.hour()))
+        .replace("{mm}", &format!("{:02}", now.minute()))
+        .replace("{ss}", &format!("{:02}", now.second()));
+
+    // 只在用到时计算
+    if name.contains("{hash}") {
+        name = name.replace("{hash}", &short_hash(bytes));
+    }
+    if name.contains("{uuid}") {
+        name = name.replace("{uuid}", &uuid());
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(policy: RenamePolicy, template: &str) -> RenameOptions {
+        RenameOptions {
+            policy,
+            template: template.to_owned(),
+        }
+    }
+
+    #[test]
+    fn original_keeps_filename() {
+        let options = options(RenamePolicy::Original, K_DEFAULT_TEMPLATE);
+        assert_eq!(
+            remote_filename("a.png", b"", ImageFormat::Png, &options),
+            None
+        );
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        let options = options(RenamePolicy::Hash, "");
+        let name = remote_filename("a.png", b"image", ImageFormat::Jpeg, &options).unwrap();
+        assert_eq!(
+            name,
+            format!("{}.jpg", &dedup::DedupIndex::content_hash(b"image")[..16])
+        );
+        assert_eq!(
+            remote_filename("b.png", b"image", ImageFormat::Jpeg, &options),
+            Some(name)
+        );
+    }
+
+    #[test]
+    fn uuid_is_random() {
+        let options = options(RenamePolicy::Uuid, "");
+        let a = remote_filename("a.png", b"", ImageFormat::Png, &options).unwrap();
+        let b = remote_filename("a.png", b"", ImageFormat::Png, &options).unwrap();
+        assert_eq!(a.len(), 32 + ".png".len());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn template_placeholders() {
+        let options = options(RenamePolicy::Template, "{name}-{yyyy}{MM}{dd}-{HH}{mm}{ss}");
+        let name = remote_filename("dir/photo.v2.jpg", b"", ImageFormat::WebP, &options).unwrap();
+
+        let (prefix, rest) = name.split_at("photo.v2-".len());
+        assert_eq!(prefix, "photo.v2-");
+        let (date, ext) = rest.split_at("yyyyMMdd-HHmmss".len());
+        assert_eq!(ext, ".webp");
+        assert!(date.chars().enumerate().all(|(i, c)| if i == 8 {
+            c == '-'
+        } else {
+            c.is_ascii_digit()
+        }));
+    }
+
+    #[test]
+    fn template_hash_and_uuid() {
+        let options = options(RenamePolicy::Template, "{hash}_{uuid}");
+        let name = remote_filename("a.png", b"image", ImageFormat::Png, &options).unwrap();
+        let (hash, uuid) = name.trim_end_matches(".png").split_once('_').unwrap();
+        assert_eq!(hash, &dedup::DedupIndex::content_hash(b"image")[..16]);
+        assert_eq!(uuid.len(), 32);
+    }
+
+    #[test]
+    fn template_is_sanitized() {
+        let options = options(RenamePolicy::Template, "../{name}\\x");
+        assert_eq!(
+            remote_filename("a.png", b"", ImageFormat::Png, &options).as_deref(),
+            Some(".._a_x.png")
+        );
+    }
+
+    #[test]
+    fn empty_template_falls_back_to_uuid() {
+        let options = options(RenamePolicy::Template, " ");
+        let name = remote_filename("a.png", b"", ImageFormat::Png, &options).unwrap();
+        assert_eq!(name.len(), 32 + ".png".len());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use globset::{GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::i18n::t;
+
+/// 没有指定 include 时默认上传的图片
+pub const K_DEFAULT_INCLUDE: &str = "*.{jpg,jpeg,png,gif,bmp,webp,svg,tif,tiff}";
+
+/// 扫描文件夹的设置
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanOptions {
+    /// 相对于扫描目录匹配，为空时使用 `K_DEFAULT_INCLUDE`
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// 最大深度，0 表示只扫描当前目录
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+}
+
+pub fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|err| {
+                anyhow::anyhow!(t!("scan.invalid_pattern", pattern = pattern, error = err))
+            })?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// 扫描结果，无法读取的文件和文件夹会被跳过
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    pub files: Vec<PathBuf>,
+    /// 跳过的路径和原因
+    pub warnings: Vec<String>,
+}
+
+/// 扫描进度，在后台扫描时由界面线程读取
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    visited: AtomicUsize,
+    found: AtomicUsize,
+}
+
+impl ScanProgress {
+    /// 已经检查的文件数量和其中符合规则的数量
+    pub fn get(&self) -> (usize, usize) {
+        (
+            self.visited.load(Ordering::Relaxed),
+            self.found.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// 递归扫描 `root` 下所有符合规则的文件，按路径排序
+///
+/// `root` 本身无法读取时返回错误，其他无法读取的路径跳过并记录在 warnings 中
+pub fn scan(
+    root: &Path,
+    options: &ScanOptions,
+    progress: &ScanProgress,
+) -> anyhow::Result<ScanResult> {
+    let include = if options.include.is_empty() {
+        build_glob_set(&[K_DEFAULT_INCLUDE.to_owned()])?
+    } else {
+        build_glob_set(&options.include)?
+    };
+    let exclude = build_glob_set(&options.exclude)?;
+
+    let mut walker = walkdir::WalkDir::new(root).follow_links(options.follow_symlinks);
+    if let Some(max_depth) = options.max_depth {
+        // walkdir 的深度从 root 本身开始算
+        walker = walker.max_depth(max_depth + 1);
+    }
+
+    let mut result = ScanResult::default();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if err.depth() == 0 => return Err(err.into()),
+            Err(err) => {
+                // walkdir 的错误信息中已经带有路径，只使用 IO 错误本身
+                let path = err.path().unwrap_or(root).display().to_string();
+                let error = match err.io_error() {
+                    Some(io_err) => io_err.to_string(),
+                    None => err.to_string(),
+                };
+                result
+                    .warnings
+                    .push(t!("scan.skipped", path = path, error = error));
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        progress.visited.fetch_add(1, Ordering::Relaxed);
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if include.is_match(relative) && !exclude.is_match(relative) {
+            progress.found.fetch_add(1, Ordering::Relaxed);
+            result.files.push(entry.into_path());
+        }
+    }
+
+    result.files.sort();
+    Ok(result)
+}
+
+/// 解析以逗号或换行分隔的匹配规则
+pub fn parse_patterns(text: &str) -> Vec<String> {
+    text.split([',', '\n'])
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在临时目录中创建文件，返回根目录
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("sm-ms-rs-scan-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        root
+    }
+
+    fn scan_names(root: &Path, options: &ScanOptions) -> Vec<String> {
+        let result = scan(root, options, &ScanProgress::default()).unwrap();
+        result
+            .files
+            .iter()
+            .map(|path| {
+                path.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_include_matches_images() {
+        let root = tree("default", &["a.jpg", "b.PNG", "c.txt", "sub/d.webp"]);
+        assert_eq!(
+            scan_names(&root, &ScanOptions::default()),
+            ["a.jpg", "b.PNG", "sub/d.webp"]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn include_exclude_and_depth() {
+        let root = tree(
+            "filter",
+            &["a.jpg", "b.png", "raw/c.jpg", "sub/d.jpg", "sub/deep/e.jpg"],
+        );
+        let mut options = ScanOptions {
+            include: parse_patterns("*.jpg"),
+            exclude: parse_patterns("raw/**"),
+            ..Default::default()
+        };
+        assert_eq!(
+            scan_names(&root, &options),
+            ["a.jpg", "sub/d.jpg", "sub/deep/e.jpg"]
+        );
+
+        options.max_depth = Some(1);
+        assert_eq!(scan_names(&root, &options), ["a.jpg", "sub/d.jpg"]);
+
+        options.max_depth = Some(0);
+        assert_eq!(scan_names(&root, &options), ["a.jpg"]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn progress_counts_files() {
+        let root = tree("progress", &["a.jpg", "b.txt"]);
+        let progress = ScanProgress::default();
+        scan(&root, &ScanOptions::default(), &progress).unwrap();
+        assert_eq!(progress.get(), (2, 1));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn missing_root_is_an_error() {
+        let root = std::env::temp_dir().join("sm-ms-rs-scan-missing");
+        assert!(scan(&root, &ScanOptions::default(), &ScanProgress::default()).is_err());
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        assert!(build_glob_set(&["a[".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn parse_patterns_splits_and_trims() {
+        assert_eq!(
+            parse_patterns(" *.jpg, *.png\n\nraw/** ,"),
+            ["*.jpg", "*.png", "raw/**"]
+        );
+    }
+}
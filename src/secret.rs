@@ -0,0 +1,168 @@
+use anyhow::Context;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::i18n::t;
+
+/// 没有图形界面时通过这个环境变量提供解锁密码
+pub const K_PASSPHRASE_ENV: &str = "SM_MS_PASSPHRASE";
+
+/// 本机密钥的前缀，避免和其他程序使用 machine id 派生出相同的密钥
+const K_MACHINE_KEY_CONTEXT: &str = "sm-ms-rs token";
+
+/// 派生密钥的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeySource {
+    /// 用户输入的密码
+    Passphrase,
+    /// 本机的 machine id，文件复制到其他电脑后无法解密
+    Machine,
+}
+
+/// 加密后的 token，使用 Argon2id 派生密钥，XChaCha20-Poly1305 加密
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedToken {
+    pub key: KeySource,
+    /// 以下均为 hex
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl EncryptedToken {
+    /// `passphrase` 为空时使用本机密钥
+    pub fn encrypt(token: &str, passphrase: Option<&str>) -> anyhow::Result<Self> {
+        let (key, secret) = match passphrase {
+            Some(passphrase) => (KeySource::Passphrase, passphrase.as_bytes().to_vec()),
+            None => (KeySource::Machine, machine_secret()?),
+        };
+
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = cipher(&secret, &salt)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, token.as_bytes())
+            .map_err(|_| anyhow::anyhow!(t!("secret.encrypt_failed")))?;
+
+        Ok(Self {
+            key,
+            salt: to_hex(&salt),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        })
+    }
+
+    /// 使用密码加密时需要提供 `passphrase`
+    pub fn decrypt(&self, passphrase: Option<&str>) -> anyhow::Result<String> {
+        let secret = match self.key {
+            KeySource::Passphrase => passphrase
+                .context(t!("secret.passphrase_required", env = K_PASSPHRASE_ENV))?
+                .as_bytes()
+                .to_vec(),
+            KeySource::Machine => machine_secret()?,
+        };
+
+        let nonce = from_hex(&self.nonce)?;
+        if nonce.len() != 24 {
+            anyhow::bail!(t!("secret.invalid"));
+        }
+        let cipher = cipher(&secret, &from_hex(&self.salt)?)?;
+        let plaintext = cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                from_hex(&self.ciphertext)?.as_ref(),
+            )
+            .map_err(|_| match self.key {
+                KeySource::Passphrase => anyhow::anyhow!(t!("secret.wrong_passphrase")),
+                KeySource::Machine => anyhow::anyhow!(t!("secret.machine_key_failed")),
+            })?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    pub fn needs_passphrase(&self) -> bool {
+        self.key == KeySource::Passphrase
+    }
+}
+
+/// 环境变量中的解锁密码
+pub fn env_passphrase() -> Option<String> {
+    std::env::var(K_PASSPHRASE_ENV)
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+fn cipher(secret: &[u8], salt: &[u8]) -> anyhow::Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(secret, salt, &mut key)
+        .map_err(|err| anyhow::anyhow!(t!("secret.derive_failed", error = err)))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn machine_secret() -> anyhow::Result<Vec<u8>> {
+    let id =
+        machine_uid::get().map_err(|err| anyhow::anyhow!(t!("secret.machine_id", error = err)))?;
+    Ok(format!("{}:{}", K_MACHINE_KEY_CONTEXT, id.trim()).into_bytes())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        anyhow::bail!(t!("secret.invalid_hex"));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&text[i..i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passphrase_round_trip() {
+        let encrypted = EncryptedToken::encrypt("token", Some("passphrase")).unwrap();
+        assert!(encrypted.needs_passphrase());
+        assert_eq!(encrypted.decrypt(Some("passphrase")).unwrap(), "token");
+        assert!(encrypted.decrypt(Some("wrong")).is_err());
+        assert!(encrypted.decrypt(None).is_err());
+    }
+
+    #[test]
+    fn round_trip_through_json() {
+        let encrypted = EncryptedToken::encrypt("token", Some("passphrase")).unwrap();
+        let json = serde_json::to_string(&encrypted).unwrap();
+        let decoded: EncryptedToken = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.decrypt(Some("passphrase")).unwrap(), "token");
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails() {
+        let mut encrypted = EncryptedToken::encrypt("token", Some("passphrase")).unwrap();
+        let flipped = if encrypted.ciphertext.starts_with('0') {
+            "1"
+        } else {
+            "0"
+        };
+        encrypted.ciphertext.replace_range(..1, flipped);
+        assert!(encrypted.decrypt(Some("passphrase")).is_err());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = [0x00, 0x7f, 0xff, 0x10];
+        assert_eq!(to_hex(&bytes), "007fff10");
+        assert_eq!(from_hex("007fff10").unwrap(), bytes);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
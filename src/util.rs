@@ -1,5 +1,10 @@
+use std::sync::OnceLock;
+
 use eframe::egui;
 use image::GenericImageView;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{font, i18n::t};
 
 pub fn load_app_icon() -> eframe::IconData {
     let app_icon_bytes = include_bytes!("../data/icon.png");
@@ -13,26 +18,21 @@ pub fn load_app_icon() -> eframe::IconData {
     }
 }
 
-pub fn setup_custom_fonts(ctx: &egui::Context) {
+/// 加载支持中文的字体，`font_path` 为空时自动查找，返回使用的字体
+pub fn setup_custom_fonts(ctx: &egui::Context, font_path: &str) -> String {
     // 从默认字体开始（我们将添加而不是替换它们）
     let mut fonts = egui::FontDefinitions::default();
 
-    // load system font
-    let Ok(font) = std::fs::read("c:/Windows/Fonts/msyh.ttc") else {
-      panic!("font not find");
-  };
+    // 找不到时中文会显示为方框，但是不影响使用
+    let Some(font) = font::find(font_path) else {
+        eprintln!("{}", t!("font.not_found"));
+        ctx.set_fonts(fonts);
+        return t!("font.default").to_owned();
+    };
 
     fonts
         .font_data
-        .insert("my_font".to_owned(), egui::FontData::from_owned(font));
-
-    // 安装我的字体
-    // fonts.font_data.insert(
-    //     "my_font".to_owned(),
-    //     egui::FontData::from_owned(include_bytes!(
-    //         "../font/YeZiGongChangChuanQiuShaXingKai-2.ttf"
-    //     )),
-    // );
+        .insert("my_font".to_owned(), egui::FontData::from_owned(font.data));
 
     // 对于比例文本，将我的字体放在第一位（最高优先级）
     fonts
@@ -50,17 +50,82 @@ pub fn setup_custom_fonts(ctx: &egui::Context) {
 
     // 告诉 egui 使用这些字体
     ctx.set_fonts(fonts);
+    font.name
+}
+
+/// 本地时区，time 在有多个线程时无法读取（Linux 和 macOS），启动时读取一次
+static LOCAL_OFFSET: OnceLock<time::UtcOffset> = OnceLock::new();
+
+/// 在 main 的开头、启动其他线程之前调用
+pub fn init_local_offset() {
+    let offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+    let _ = LOCAL_OFFSET.set(offset);
 }
 
-#[allow(clippy::needless_pass_by_value)]
-pub fn parse_ehttp_response(response: ehttp::Response) -> Result<egui_extras::RetainedImage, String> {
-    let content_type = response.content_type().unwrap_or_default();
-    if content_type.starts_with("image/") {
-        egui_extras::RetainedImage::from_image_bytes(&response.url, &response.bytes)
+/// 当前的本地时间，无法确定本地时区时使用 UTC
+pub fn now_local() -> time::OffsetDateTime {
+    let offset = LOCAL_OFFSET.get().copied().unwrap_or(time::UtcOffset::UTC);
+    time::OffsetDateTime::now_utc().to_offset(offset)
+}
+
+/// 从标准输入读取或者从 url 下载的图片的大小上限，超过 sm.ms 限制的图片还可以在预处理时压缩
+pub const K_MAX_INPUT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// 最多读取 `limit` 字节，超过时返回错误，例如读取标准输入
+pub async fn read_limited(reader: impl AsyncRead + Unpin, limit: u64) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(limit + 1).read_to_end(&mut bytes).await?;
+    if bytes.len() as u64 > limit {
+        anyhow::bail!(t!("util.too_large", limit = human_bytes(limit)));
+    }
+    Ok(bytes)
+}
+
+/// 字节数转换为可读的字符串，例如 1.20 MB
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
     } else {
-        Err(format!(
-            "Expected image, found content-type {:?}",
-            content_type
-        ))
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
+
+/// 根据图片格式替换文件扩展名，例如 a.png -> a.jpg
+pub fn replace_extension(filename: &str, format: image::ImageFormat) -> String {
+    let ext = format.extensions_str().first().copied().unwrap_or_default();
+    std::path::Path::new(filename)
+        .with_extension(ext)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// 先写入临时文件再重命名，写入过程中崩溃不会损坏原文件
+///
+/// 临时文件名带有随机后缀，多个线程同时写入同一个文件时不会互相覆盖临时文件
+pub fn write_atomic(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", uuid::Uuid::new_v4().simple()));
+    let tmp = std::path::PathBuf::from(tmp);
+
+    let res = (|| {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, path)
+    })();
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
     }
+    res
 }
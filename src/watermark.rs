@@ -0,0 +1,235 @@
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{font, i18n::t};
+
+/// 水印距离图片边缘的距离，相对于图片短边
+const K_MARGIN_RATIO: f32 = 0.02;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatermarkKind {
+    #[default]
+    Text,
+    Logo,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    pub const ALL: [WatermarkPosition; 5] = [
+        WatermarkPosition::TopLeft,
+        WatermarkPosition::TopRight,
+        WatermarkPosition::BottomLeft,
+        WatermarkPosition::BottomRight,
+        WatermarkPosition::Center,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            WatermarkPosition::TopLeft => t!("watermark.position.top_left"),
+            WatermarkPosition::TopRight => t!("watermark.position.top_right"),
+            WatermarkPosition::BottomLeft => t!("watermark.position.bottom_left"),
+            WatermarkPosition::BottomRight => t!("watermark.position.bottom_right"),
+            WatermarkPosition::Center => t!("watermark.position.center"),
+        }
+    }
+}
+
+/// 水印设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatermarkOptions {
+    pub enabled: bool,
+    pub kind: WatermarkKind,
+    pub text: String,
+    /// 文字颜色 RGB
+    pub color: [u8; 3],
+    /// 文字字体，为空时和界面一样自动查找支持中文的字体
+    pub font_path: String,
+    /// logo 图片，为空时使用应用图标
+    pub logo_path: String,
+    pub position: WatermarkPosition,
+    /// 不透明度 0.0 ~ 1.0
+    pub opacity: f32,
+    /// 水印宽度占图片宽度的比例
+    pub scale: f32,
+}
+
+impl Default for WatermarkOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: WatermarkKind::Text,
+            text: "sm.ms".to_owned(),
+            color: [255, 255, 255],
+            font_path: Default::default(),
+            logo_path: Default::default(),
+            position: WatermarkPosition::BottomRight,
+            opacity: 0.5,
+            scale: 0.2,
+        }
+    }
+}
+
+/// 在图片上绘制水印
+pub fn apply(img: &DynamicImage, options: &WatermarkOptions) -> anyhow::Result<DynamicImage> {
+    let mut canvas = img.to_rgba8();
+
+    let mark = match options.kind {
+        WatermarkKind::Text => render_text(options, canvas.width())?,
+        WatermarkKind::Logo => load_logo(options, canvas.width())?,
+    };
+
+    let (x, y) = place(&canvas, &mark, options.position);
+    overlay(&mut canvas, &mark, x, y, options.opacity.clamp(0.0, 1.0));
+
+    // 没有透明通道的图片保持原来的颜色类型
+    let out = DynamicImage::ImageRgba8(canvas);
+    Ok(if img.color().has_alpha() {
+        out
+    } else {
+        DynamicImage::ImageRgb8(out.to_rgb8())
+    })
+}
+
+fn target_width(image_width: u32, options: &WatermarkOptions) -> u32 {
+    ((image_width as f32 * options.scale.clamp(0.01, 1.0)) as u32).max(1)
+}
+
+fn load_logo(options: &WatermarkOptions, image_width: u32) -> anyhow::Result<RgbaImage> {
+    let logo = if options.logo_path.is_empty() {
+        image::load_from_memory(include_bytes!("../data/icon.png"))?
+    } else {
+        image::open(&options.logo_path)?
+    };
+
+    let (w, h) = logo.dimensions();
+    let width = target_width(image_width, options);
+    let height = ((h as f32 * width as f32 / w as f32) as u32).max(1);
+
+    Ok(logo
+        .resize_exact(width, height, FilterType::Lanczos3)
+        .to_rgba8())
+}
+
+fn load_font(options: &WatermarkOptions) -> anyhow::Result<FontVec> {
+    if !options.font_path.is_empty() {
+        return Ok(FontVec::try_from_vec(std::fs::read(&options.font_path)?)?);
+    }
+
+    let Some(font) = font::find("") else {
+        anyhow::bail!(t!("watermark.no_font"));
+    };
+    Ok(FontVec::try_from_vec(font.data)?)
+}
+
+/// 把文字渲染成透明背景的图片，宽度按 `scale` 计算
+fn render_text(options: &WatermarkOptions, image_width: u32) -> anyhow::Result<RgbaImage> {
+    if options.text.trim().is_empty() {
+        anyhow::bail!(t!("watermark.empty_text"));
+    }
+
+    let font = load_font(options)?;
+
+    // 先用固定字号测量宽度，再按目标宽度换算字号
+    let measure = |px: f32| {
+        let font = font.as_scaled(PxScale::from(px));
+        let mut width = 0.0;
+        let mut last = None;
+        for c in options.text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(last) = last {
+                width += font.kern(last, id);
+            }
+            width += font.h_advance(id);
+            last = Some(id);
+        }
+        width
+    };
+
+    let base_width = measure(100.0);
+    if base_width <= 0.0 {
+        anyhow::bail!(t!("watermark.render_failed"));
+    }
+    let px = 100.0 * target_width(image_width, options) as f32 / base_width;
+
+    let scaled = font.as_scaled(PxScale::from(px));
+    let width = measure(px).ceil() as u32 + 1;
+    let height = (scaled.ascent() - scaled.descent()).ceil() as u32 + 1;
+    let [r, g, b] = options.color;
+    let mut canvas = RgbaImage::new(width, height);
+
+    let mut caret = 0.0;
+    let mut last = None;
+    for c in options.text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(last) = last {
+            caret += scaled.kern(last, id);
+        }
+        let glyph = id.with_scale_and_position(px, ab_glyph::point(caret, scaled.ascent()));
+        caret += scaled.h_advance(id);
+        last = Some(id);
+
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|x, y, coverage| {
+            let x = x as i32 + bounds.min.x as i32;
+            let y = y as i32 + bounds.min.y as i32;
+            if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                return;
+            }
+
+            let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+            let alpha = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+            *pixel = Rgba([r, g, b, pixel[3].max(alpha)]);
+        });
+    }
+
+    Ok(canvas)
+}
+
+fn place(canvas: &RgbaImage, mark: &RgbaImage, position: WatermarkPosition) -> (i64, i64) {
+    let (w, h) = (canvas.width() as i64, canvas.height() as i64);
+    let (mw, mh) = (mark.width() as i64, mark.height() as i64);
+    let margin = (w.min(h) as f32 * K_MARGIN_RATIO) as i64;
+
+    match position {
+        WatermarkPosition::TopLeft => (margin, margin),
+        WatermarkPosition::TopRight => (w - mw - margin, margin),
+        WatermarkPosition::BottomLeft => (margin, h - mh - margin),
+        WatermarkPosition::BottomRight => (w - mw - margin, h - mh - margin),
+        WatermarkPosition::Center => ((w - mw) / 2, (h - mh) / 2),
+    }
+}
+
+/// 按 alpha 混合把 `mark` 叠加到 `canvas` 的 (x, y) 位置
+fn overlay(canvas: &mut RgbaImage, mark: &RgbaImage, x: i64, y: i64, opacity: f32) {
+    for (mx, my, src) in mark.enumerate_pixels() {
+        let (cx, cy) = (x + mx as i64, y + my as i64);
+        if cx < 0 || cy < 0 || cx >= canvas.width() as i64 || cy >= canvas.height() as i64 {
+            continue;
+        }
+
+        let alpha = src[3] as f32 / 255.0 * opacity;
+        if alpha <= 0.0 {
+            continue;
+        }
+
+        let dst = canvas.get_pixel_mut(cx as u32, cy as u32);
+        for i in 0..3 {
+            dst[i] = (src[i] as f32 * alpha + dst[i] as f32 * (1.0 - alpha)).round() as u8;
+        }
+        dst[3] = (alpha * 255.0 + dst[3] as f32 * (1.0 - alpha)).round() as u8;
+    }
+}
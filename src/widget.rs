@@ -11,9 +11,24 @@ pub fn error_label(ui: &mut Ui, text: impl Into<String>) -> Response {
     ui.label(RichText::new(text).size(20.0).color(Color32::RED))
 }
 
+pub fn warning_label(ui: &mut Ui, text: impl Into<String>) -> Response {
+    ui.label(RichText::new(text).color(Color32::from_rgb(230, 140, 0)))
+}
+
 pub fn info_row(ui: &mut Ui, l: impl Into<String>, r: impl Into<String>) {
     ui.horizontal(|ui| {
         ui.label(RichText::new(l).size(20.0));
         ui.label(RichText::new(r).size(20.0));
     });
 }
+
+pub fn warning_row(ui: &mut Ui, l: impl Into<String>, r: impl Into<String>) {
+    ui.horizontal(|ui| {
+        ui.label(RichText::new(l).size(20.0));
+        ui.label(
+            RichText::new(r)
+                .size(20.0)
+                .color(Color32::from_rgb(230, 140, 0)),
+        );
+    });
+}